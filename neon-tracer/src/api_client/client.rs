@@ -45,10 +45,16 @@ pub struct Client {
 impl Client {
     /// Creates a new [`NeonAPIClient`].
     pub fn new(config: Arc<Config>, neon_api_url: impl Into<String>) -> Client {
+        // no request may outlive the longest timeout a client can ask for
+        let http_client = ReqwestClient::builder()
+            .timeout(config.timeouts.max)
+            .build()
+            .expect("Failed to build HTTP client for Neon API");
+
         Client {
             config,
             neon_api_url: neon_api_url.into(),
-            http_client: Arc::new(ReqwestClient::new()),
+            http_client: Arc::new(http_client),
         }
     }
 
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use solana_sdk::pubkey::Pubkey;
 
@@ -7,6 +7,64 @@ pub struct Config {
     pub neon_api_url: String,
     pub chain_id: u64,
     pub token_mint: Pubkey,
+    /// Neon EVM program owning the Neon accounts in the account dumps
+    pub evm_loader: Pubkey,
+    pub timeouts: Timeouts,
+    pub step_limits: StepLimits,
+    /// Number of block transactions traced at once, zero traces blocks by a single request
+    pub block_trace_concurrency: usize,
+    /// Memory a JS tracer may allocate on top of the trace data, in bytes
+    pub js_tracer_memory: u64,
+}
+
+/// Methods sharing the same default limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodClass {
+    /// `eth_call` and the state queries
+    Call,
+    /// Tracing of a single transaction or call
+    Trace,
+    /// Tracing of whole blocks
+    BlockTrace,
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    pub call: Duration,
+    pub trace: Duration,
+    pub block_trace: Duration,
+    /// Upper bound of the geth `timeout` option supplied by clients
+    pub max: Duration,
+}
+
+impl Timeouts {
+    pub fn default_for(&self, class: MethodClass) -> Duration {
+        match class {
+            MethodClass::Call => self.call,
+            MethodClass::Trace => self.trace,
+            MethodClass::BlockTrace => self.block_trace,
+        }
+    }
+}
+
+/// Numbers of EVM steps Neon API may execute per emulation
+#[derive(Debug, Clone)]
+pub struct StepLimits {
+    pub call: u64,
+    pub trace: u64,
+    pub block_trace: u64,
+    /// Upper bound of the step limit requested by clients
+    pub max: u64,
+}
+
+impl StepLimits {
+    pub fn default_for(&self, class: MethodClass) -> u64 {
+        match class {
+            MethodClass::Call => self.call,
+            MethodClass::Trace => self.trace,
+            MethodClass::BlockTrace => self.block_trace,
+        }
+    }
 }
 
 pub fn read_api_client_config_from_enviroment() -> Config {
@@ -20,14 +78,50 @@ pub fn read_api_client_config_from_enviroment() -> Config {
     let token_mint = read_env("NEON_TOKEN_MINT");
     let token_mint = Pubkey::from_str(token_mint.as_str())
         .unwrap_or_else(|_| panic!("Failed to parse NEON_TOKEN_MINT {token_mint}"));
+    let evm_loader = std::env::var("EVM_LOADER")
+        .unwrap_or_else(|_| "eeLSJgWzzxrqKv1UxtRVVH8FX3qCQWUs9QuAjJpETGU".to_string());
+    let evm_loader = Pubkey::from_str(evm_loader.as_str())
+        .unwrap_or_else(|_| panic!("Failed to parse EVM_LOADER {evm_loader}"));
     let chain_id = read_env("NEON_CHAIN_ID");
     let chain_id = chain_id
         .parse::<u64>()
         .unwrap_or_else(|_| panic!("Failed to parse NEON_CHAIN_ID"));
 
+    let read_u64 = |var_name: &str, default: u64| {
+        std::env::var(var_name).map_or(default, |value| {
+            value
+                .parse::<u64>()
+                .unwrap_or_else(|_| panic!("Failed to parse {var_name} {value}"))
+        })
+    };
+    let read_timeout =
+        |var_name: &str, default: u64| Duration::from_secs(read_u64(var_name, default));
+    let timeouts = Timeouts {
+        call: read_timeout("CALL_TIMEOUT_SEC", 10),
+        trace: read_timeout("TRACE_TIMEOUT_SEC", 10),
+        block_trace: read_timeout("BLOCK_TRACE_TIMEOUT_SEC", 30),
+        max: read_timeout("MAX_TIMEOUT_SEC", 60),
+    };
+    let step_limits = StepLimits {
+        call: read_u64("CALL_STEPS_TO_EXECUTE", 500_000),
+        trace: read_u64("TRACE_STEPS_TO_EXECUTE", 500_000),
+        block_trace: read_u64("BLOCK_TRACE_STEPS_TO_EXECUTE", 500_000),
+        max: read_u64("MAX_STEPS_TO_EXECUTE", 2_000_000),
+    };
+
+    let block_trace_concurrency = read_u64("BLOCK_TRACE_CONCURRENCY", 0)
+        .try_into()
+        .unwrap_or_else(|_| panic!("Failed to parse BLOCK_TRACE_CONCURRENCY"));
+    let js_tracer_memory = read_u64("JS_TRACER_MEMORY_MB", 256).saturating_mul(1024 * 1024);
+
     Config {
         neon_api_url,
         chain_id,
         token_mint,
+        evm_loader,
+        timeouts,
+        step_limits,
+        block_trace_concurrency,
+        js_tracer_memory,
     }
 }
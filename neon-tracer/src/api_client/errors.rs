@@ -21,3 +21,14 @@ pub enum NeonAPIClientError {
     #[error("NeonApiError: {0}")]
     NeonApiError(String),
 }
+
+impl NeonAPIClientError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::ReqwestError(e) if e.is_timeout())
+    }
+
+    /// Neon API stops the emulation with `TooManySteps` once the step limit is reached
+    pub fn is_step_limit(&self) -> bool {
+        matches!(self, Self::NeonApiError(e) if e.to_lowercase().contains("too many steps"))
+    }
+}
@@ -10,6 +10,13 @@ pub struct Options {
     pub web3_proxy: String,
     pub metrics_ip: Ipv4Addr,
     pub metrics_port: u16,
+    /// Record the flat call traces of new blocks for `trace_filter`
+    pub trace_index_enabled: bool,
+    /// Slot to start indexing from when the index is empty, the latest slot by default
+    pub trace_index_start_slot: Option<u64>,
+    pub trace_index_interval_sec: u64,
+    /// Size limit of a JSON-RPC response in bytes
+    pub max_response_size: usize,
 }
 
 pub fn read_config() -> Options {
@@ -59,11 +66,34 @@ pub fn read_config() -> Options {
         .parse::<u16>()
         .unwrap_or_else(|_| panic!("Failed to parse metrics port {metrics_port}"));
 
+    let trace_index_enabled = std::env::var("TRACE_INDEX_ENABLED")
+        .map(|enabled| enabled == "true" || enabled == "1")
+        .unwrap_or(false);
+    let trace_index_start_slot = std::env::var("TRACE_INDEX_START_SLOT").ok().map(|slot| {
+        slot.parse::<u64>()
+            .unwrap_or_else(|_| panic!("Failed to parse TRACE_INDEX_START_SLOT {slot}"))
+    });
+    let trace_index_interval_sec =
+        std::env::var("TRACE_INDEX_INTERVAL_SEC").unwrap_or_else(|_| "5".to_string());
+    let trace_index_interval_sec = trace_index_interval_sec.parse::<u64>().unwrap_or_else(|_| {
+        panic!("Failed to parse TRACE_INDEX_INTERVAL_SEC {trace_index_interval_sec}")
+    });
+
+    let max_response_size =
+        std::env::var("MAX_RESPONSE_SIZE").unwrap_or_else(|_| (10 * 1024 * 1024).to_string());
+    let max_response_size = max_response_size
+        .parse::<usize>()
+        .unwrap_or_else(|_| panic!("Failed to parse MAX_RESPONSE_SIZE {max_response_size}"));
+
     Options {
         addr,
         db_config,
         web3_proxy,
         metrics_ip,
         metrics_port,
+        trace_index_enabled,
+        trace_index_start_slot,
+        trace_index_interval_sec,
+        max_response_size,
     }
 }
@@ -0,0 +1,169 @@
+//! Neon accounts read from the account dumps of the tracer DB.
+//!
+//! The dumper records every update of a Solana account into `events.update_account_distributed`
+//! along with the slot and the write version of the update. The Neon EVM program keeps an
+//! Ethereum account in a Solana account with the first storage slots of the contract inline,
+//! the rest of the slots are kept by the storage cell accounts, 256 slots per cell.
+
+use ethnum::U256;
+use evm_loader::{
+    account::{ether_account, ether_storage, Packable},
+    config::STORAGE_ENTRIES_IN_CONTRACT_ACCOUNT,
+};
+use neon_cli_lib::types::{Address, ChResult};
+
+use super::DataSource;
+
+/// Length of the account tag preceding the account data
+const TAG_LEN: usize = 1;
+
+/// Length of the account tag followed by the Ethereum address, the common prefix
+/// of the Ethereum accounts and their storage cells
+const ADDRESS_PREFIX_LEN: usize = TAG_LEN + 20;
+
+/// Length of a storage cell entry: the slot subindex and the value
+const STORAGE_CELL_ENTRY_LEN: usize = 1 + 32;
+
+impl DataSource {
+    /// Returns the keys of the non-empty storage slots of `address` at the end of `slot`
+    pub async fn get_storage_keys(&self, address: &Address, slot: u64) -> ChResult<Vec<U256>> {
+        let prefixes = vec![
+            address_prefix(ether_account::Data::TAG, address),
+            address_prefix(ether_storage::Data::TAG, address),
+        ];
+        // the latest version of every account which ever held the contract or its storage
+        let accounts = self
+            .tracer_db
+            .client
+            .query(
+                "SELECT data FROM (
+                     SELECT argMax(data, (slot, write_version)) AS data
+                     FROM events.update_account_distributed
+                     WHERE slot <= ? AND pubkey IN (
+                         SELECT DISTINCT pubkey FROM events.update_account_distributed
+                         WHERE owner = ? AND has(?, arraySlice(data, 1, ?))
+                     )
+                     GROUP BY pubkey
+                 ) WHERE has(?, arraySlice(data, 1, ?))",
+            )
+            .bind(slot)
+            .bind(self.neon_api.config.evm_loader.as_ref())
+            .bind(&prefixes)
+            .bind(ADDRESS_PREFIX_LEN)
+            .bind(&prefixes)
+            .bind(ADDRESS_PREFIX_LEN)
+            .fetch_all::<Vec<u8>>()
+            .await?;
+
+        let Some(contract) = accounts.iter().find(|data| {
+            data[0] == ether_account::Data::TAG && data.len() >= TAG_LEN + ether_account::Data::SIZE
+        }) else {
+            return Ok(vec![]);
+        };
+        let contract_data = ether_account::Data::unpack(&contract[TAG_LEN..]);
+
+        let mut keys = inline_storage_keys(&contract_data, contract);
+        for cell in accounts
+            .iter()
+            .filter(|data| data[0] == ether_storage::Data::TAG)
+        {
+            keys.extend(storage_cell_keys(&contract_data, cell));
+        }
+
+        Ok(keys)
+    }
+
+    /// Returns the Neon accounts changed in `from_slot..=to_slot`: the Ethereum accounts
+    /// themselves and the contracts the changed storage cells belong to. An account updated
+    /// in the range is changed if its data at the end of `to_slot` differs from the data
+    /// before `from_slot`, so the accounts written back with the same state are skipped.
+    pub async fn get_modified_accounts(
+        &self,
+        from_slot: u64,
+        to_slot: u64,
+    ) -> ChResult<Vec<Address>> {
+        let tags = vec![ether_account::Data::TAG, ether_storage::Data::TAG];
+        let addresses = self
+            .tracer_db
+            .client
+            .query(
+                "SELECT DISTINCT arraySlice(data, ?, 20) AS address FROM (
+                     SELECT if(length(after) > 0, after, before) AS data, after, before
+                     FROM (
+                         SELECT argMaxIf(data, (slot, write_version), slot >= ?) AS after,
+                                argMaxIf(data, (slot, write_version), slot < ?) AS before
+                         FROM events.update_account_distributed
+                         WHERE slot <= ? AND pubkey IN (
+                             SELECT DISTINCT pubkey FROM events.update_account_distributed
+                             WHERE owner = ? AND slot >= ? AND slot <= ?
+                         )
+                         GROUP BY pubkey
+                     )
+                 )
+                 WHERE after != before AND length(data) >= ? AND has(?, data[1])
+                 ORDER BY address",
+            )
+            .bind(TAG_LEN + 1)
+            .bind(from_slot)
+            .bind(from_slot)
+            .bind(to_slot)
+            .bind(self.neon_api.config.evm_loader.as_ref())
+            .bind(from_slot)
+            .bind(to_slot)
+            .bind(ADDRESS_PREFIX_LEN)
+            .bind(&tags)
+            .fetch_all::<Vec<u8>>()
+            .await?;
+
+        Ok(addresses
+            .into_iter()
+            .filter_map(|address| <[u8; 20]>::try_from(address).ok())
+            .map(Address::from)
+            .collect())
+    }
+}
+
+fn address_prefix(tag: u8, address: &Address) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(ADDRESS_PREFIX_LEN);
+    prefix.push(tag);
+    prefix.extend_from_slice(&address.0);
+    prefix
+}
+
+/// Keys of the non-zero slots kept in the contract account
+fn inline_storage_keys(contract: &ether_account::Data, account: &[u8]) -> Vec<U256> {
+    // accounts without code have no storage
+    if contract.code_size == 0 {
+        return vec![];
+    }
+
+    let offset = TAG_LEN + ether_account::Data::SIZE;
+    account
+        .get(offset..offset + 32 * STORAGE_ENTRIES_IN_CONTRACT_ACCOUNT)
+        .unwrap_or_default()
+        .chunks_exact(32)
+        .enumerate()
+        .filter(|(_, value)| value.iter().any(|byte| *byte != 0))
+        .map(|(index, _)| U256::from(index as u64))
+        .collect()
+}
+
+/// Keys of the non-zero slots kept in the storage cell, the cells of the previous
+/// generations of the contract belong to the destroyed code and are skipped
+fn storage_cell_keys(contract: &ether_account::Data, account: &[u8]) -> Vec<U256> {
+    let offset = TAG_LEN + ether_storage::Data::SIZE;
+    if account.len() < offset {
+        return vec![];
+    }
+
+    let cell = ether_storage::Data::unpack(&account[TAG_LEN..]);
+    if cell.generation != contract.generation {
+        return vec![];
+    }
+
+    account[offset..]
+        .chunks_exact(STORAGE_CELL_ENTRY_LEN)
+        .filter(|entry| entry[1..].iter().any(|byte| *byte != 0))
+        .map(|entry| cell.index | U256::from(entry[0]))
+        .collect()
+}
@@ -0,0 +1,399 @@
+//! Index of the flat call traces served by `trace_filter`.
+//!
+//! The indexer replays every new block through Neon API once and records its flattened calls
+//! into `events.call_traces`, next to the tables populated by the Neon indexer.
+//! A slot which fails to be indexed is retried a few times, then it is recorded into
+//! `events.call_traces_failed_slots` along with the error and skipped, so the index keeps
+//! advancing.
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use clickhouse::Row;
+use neon_cli_lib::types::{Address, ChResult};
+use serde::Serialize;
+use tokio::sync::mpsc::Receiver;
+use tracing::{info, warn};
+use web3::types::{BlockId, BlockNumber};
+
+use super::DataSource;
+use crate::{
+    api_client::config::MethodClass,
+    metrics,
+    service::Result,
+    stop_handle::StopHandle,
+    tracers::{
+        localized_traces, parity_trace_config, Action, CallOutput, FlatTraceResult, LocalizedTrace,
+        TxContext, TxLocation,
+    },
+};
+
+/// Maximal number of slots indexed in one iteration
+const SLOTS_PER_ITERATION: u64 = 100;
+
+/// Number of attempts to index a slot before it is skipped
+const SLOT_ATTEMPTS: u32 = 3;
+
+const CREATE_CALL_TRACES: &str = "
+    CREATE TABLE IF NOT EXISTS events.call_traces (
+        slot UInt64,
+        tx_hash String,
+        tx_position UInt32,
+        trace_address Array(UInt32),
+        from_address String,
+        to_address String,
+        call_type LowCardinality(String),
+        value String,
+        trace String
+    ) ENGINE = ReplacingMergeTree
+    ORDER BY (slot, tx_position, trace_address)";
+
+/// Slots are recorded after all their traces, so the index is complete up to the maximal one
+const CREATE_INDEXED_SLOTS: &str = "
+    CREATE TABLE IF NOT EXISTS events.call_traces_indexed_slots (
+        slot UInt64
+    ) ENGINE = ReplacingMergeTree
+    ORDER BY slot";
+
+const CREATE_FAILED_SLOTS: &str = "
+    CREATE TABLE IF NOT EXISTS events.call_traces_failed_slots (
+        slot UInt64,
+        error String
+    ) ENGINE = ReplacingMergeTree
+    ORDER BY slot";
+
+#[derive(Row, Serialize, Debug)]
+struct CallTraceRow {
+    slot: u64,
+    tx_hash: String,
+    tx_position: u32,
+    trace_address: Vec<u32>,
+    from_address: String,
+    to_address: String,
+    call_type: String,
+    value: String,
+    /// `LocalizedTrace` serialized to JSON
+    trace: String,
+}
+
+#[derive(Row, Serialize, Debug)]
+struct FailedSlotRow {
+    slot: u64,
+    error: String,
+}
+
+#[derive(Row, Serialize, Debug)]
+struct IndexedSlotRow {
+    slot: u64,
+}
+
+impl CallTraceRow {
+    fn new(slot: u64, trace: &LocalizedTrace, id: u64) -> Result<Self> {
+        let (from, to, call_type, value) = match &trace.trace.action {
+            Action::Call(call) => (call.from, Some(call.to), call.call_type, call.value),
+            Action::Create(create) => {
+                let address = match &trace.trace.result {
+                    FlatTraceResult::Result(CallOutput::Create { address, .. }) => Some(*address),
+                    _ => None,
+                };
+                (create.from, address, "create", create.value)
+            }
+        };
+
+        let overflow = |e: std::num::TryFromIntError| {
+            super::INTERNAL_ERR(&format!("trace position does not fit the index: {e}"), id)
+        };
+        Ok(Self {
+            slot,
+            tx_hash: format!(
+                "0x{}",
+                hex::encode(trace.location.transaction_hash.0.to_be_bytes())
+            ),
+            tx_position: u32::try_from(trace.location.transaction_position).map_err(overflow)?,
+            trace_address: trace
+                .trace
+                .trace_address
+                .iter()
+                .map(|index| u32::try_from(*index))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(overflow)?,
+            from_address: address_to_string(&from),
+            to_address: to.as_ref().map(address_to_string).unwrap_or_default(),
+            call_type: call_type.to_string(),
+            value: value.to_string(),
+            trace: serde_json::to_string(trace).unwrap_or_default(),
+        })
+    }
+}
+
+/// Lowercase 0x-prefixed hex, the form the addresses are stored in
+pub fn address_to_string(address: &Address) -> String {
+    format!("0x{}", hex::encode(address.0))
+}
+
+/// Conditions of `trace_filter` with the block range resolved to slots
+#[derive(Debug, Clone)]
+pub struct CallTraceFilter {
+    pub from_slot: u64,
+    pub to_slot: u64,
+    pub from_addresses: Vec<String>,
+    pub to_addresses: Vec<String>,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+impl DataSource {
+    pub async fn create_call_index(&self) -> ChResult<()> {
+        for query in [
+            CREATE_CALL_TRACES,
+            CREATE_INDEXED_SLOTS,
+            CREATE_FAILED_SLOTS,
+        ] {
+            self.tracer_db.client.query(query).execute().await?;
+        }
+        Ok(())
+    }
+
+    /// The last slot recorded into the index
+    pub async fn get_indexed_slot(&self) -> ChResult<Option<u64>> {
+        self.tracer_db
+            .client
+            .query("SELECT maxOrNull(slot) FROM events.call_traces_indexed_slots")
+            .fetch_one::<Option<u64>>()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The first slot recorded into the index, the earlier slots are never indexed
+    pub async fn get_first_indexed_slot(&self) -> ChResult<Option<u64>> {
+        self.tracer_db
+            .client
+            .query("SELECT minOrNull(slot) FROM events.call_traces_indexed_slots")
+            .fetch_one::<Option<u64>>()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Returns the matching traces serialized to JSON, ordered the same way as in the blocks
+    pub async fn filter_call_traces(&self, filter: &CallTraceFilter) -> ChResult<Vec<String>> {
+        let mut query = String::from(
+            "SELECT trace FROM events.call_traces FINAL WHERE slot >= ? AND slot <= ?",
+        );
+        if !filter.from_addresses.is_empty() {
+            query.push_str(" AND has(?, from_address)");
+        }
+        if !filter.to_addresses.is_empty() {
+            query.push_str(" AND has(?, to_address)");
+        }
+        query.push_str(" ORDER BY slot, tx_position, trace_address LIMIT ? OFFSET ?");
+
+        let mut query = self
+            .tracer_db
+            .client
+            .query(&query)
+            .bind(filter.from_slot)
+            .bind(filter.to_slot);
+        if !filter.from_addresses.is_empty() {
+            query = query.bind(&filter.from_addresses);
+        }
+        if !filter.to_addresses.is_empty() {
+            query = query.bind(&filter.to_addresses);
+        }
+
+        query
+            .bind(filter.limit)
+            .bind(filter.offset)
+            .fetch_all::<String>()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Returns the first slot in `from_slot..=to_slot` skipped by the indexer
+    pub async fn get_failed_slot(&self, from_slot: u64, to_slot: u64) -> ChResult<Option<u64>> {
+        self.tracer_db
+            .client
+            .query(
+                "SELECT minOrNull(slot) FROM events.call_traces_failed_slots
+                 WHERE slot >= ? AND slot <= ?",
+            )
+            .bind(from_slot)
+            .bind(to_slot)
+            .fetch_one::<Option<u64>>()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Records the flat traces of the block at `slot`, returns the number of the traces
+    async fn index_slot(&self, slot: u64, id: u64) -> Result<usize> {
+        let block_id = BlockId::Number(BlockNumber::Number(slot.into()));
+        let block = self
+            .web3
+            .eth()
+            .block_with_txs(block_id)
+            .await
+            .map_err(|e| {
+                super::INTERNAL_ERR(
+                    &format!("failed to send eth_getBlockByNumber to proxy, {e:?}"),
+                    id,
+                )
+            })?;
+
+        // skipped slots and blocks without Neon transactions have nothing to trace
+        let transactions = block.map(|block| block.transactions).unwrap_or_default();
+        let mut rows = vec![];
+        if !transactions.is_empty() && slot > 0 {
+            let tout = self.default_timeout(MethodClass::BlockTrace);
+            let steps = self.default_steps(MethodClass::BlockTrace);
+            let traced_calls = self
+                .neon_api
+                .trace_next_block(slot - 1, Some(parity_trace_config()), steps, &tout, id)
+                .await?;
+            super::check_traced_count(slot, transactions.len(), traced_calls.0.len(), id)?;
+
+            for (tx, traced_call) in transactions.iter().zip(traced_calls.0.iter()) {
+                let ctx = TxContext::from(tx);
+                let traces = localized_traces(traced_call, &ctx, &TxLocation::from(tx));
+                for trace in &traces {
+                    rows.push(CallTraceRow::new(slot, trace, id)?);
+                }
+            }
+        }
+
+        if !rows.is_empty() {
+            self.write_rows("events.call_traces", &rows, id).await?;
+        }
+        self.write_rows(
+            "events.call_traces_indexed_slots",
+            &[IndexedSlotRow { slot }],
+            id,
+        )
+        .await?;
+
+        Ok(rows.len())
+    }
+
+    /// Records the slot as failed and indexed, the requests covering it fail instead
+    /// of returning incomplete results
+    async fn skip_slot(&self, slot: u64, error: String, id: u64) -> Result<()> {
+        self.write_rows(
+            "events.call_traces_failed_slots",
+            &[FailedSlotRow { slot, error }],
+            id,
+        )
+        .await?;
+        self.write_rows(
+            "events.call_traces_indexed_slots",
+            &[IndexedSlotRow { slot }],
+            id,
+        )
+        .await
+    }
+
+    async fn write_rows<T: Row + Serialize>(&self, table: &str, rows: &[T], id: u64) -> Result<()> {
+        let to_ch_error = |e: clickhouse::error::Error| {
+            super::INTERNAL_ERR(&format!("failed to write the trace index: {e}"), id)
+        };
+        let mut insert = self.tracer_db.client.insert(table).map_err(to_ch_error)?;
+        for row in rows {
+            insert.write(row).await.map_err(to_ch_error)?;
+        }
+        insert.end().await.map_err(to_ch_error)
+    }
+}
+
+pub async fn run_indexer(
+    source: DataSource,
+    start_slot: Option<u64>,
+    interval: Duration,
+    mut stop_rcv: Receiver<()>,
+) {
+    info!("Starting trace indexer...");
+    if let Err(err) = source.create_call_index().await {
+        warn!("Failed to create the trace index tables: {:?}", err);
+        return;
+    }
+
+    let mut interval = tokio::time::interval(interval);
+    // failed attempts to index the slot following the indexed ones
+    let mut attempts = 0;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => index_new_slots(&source, start_slot, &mut attempts).await,
+            _ = stop_rcv.recv() => {
+                break;
+            }
+        }
+    }
+
+    info!("Trace indexer stopped.");
+}
+
+async fn index_new_slots(source: &DataSource, start_slot: Option<u64>, attempts: &mut u32) {
+    let latest_slot = match source.tracer_db.get_latest_block().await {
+        Ok(slot) => slot,
+        Err(err) => {
+            warn!(
+                "Failed to get the latest slot for the trace index: {:?}",
+                err
+            );
+            return;
+        }
+    };
+    let indexed_slot = match source.get_indexed_slot().await {
+        Ok(slot) => slot,
+        Err(err) => {
+            warn!("Failed to get the last indexed slot: {:?}", err);
+            return;
+        }
+    };
+
+    let first_slot = match indexed_slot {
+        Some(slot) => slot + 1,
+        None => start_slot.unwrap_or(latest_slot),
+    };
+    let last_slot = latest_slot.min(first_slot.saturating_add(SLOTS_PER_ITERATION - 1));
+
+    let mut indexed_slot = indexed_slot.unwrap_or(first_slot.saturating_sub(1));
+    for slot in first_slot..=last_slot {
+        let id = source.request_id.fetch_add(1, Ordering::SeqCst);
+        match source.index_slot(slot, id).await {
+            Ok(count) => {
+                info!("id {id}: indexed {count} call traces of slot {slot}");
+                *attempts = 0;
+                indexed_slot = slot;
+            }
+            Err(err) => {
+                *attempts += 1;
+                warn!(
+                    "id {id}: failed to index slot {slot}, attempt {attempts}: {:?}",
+                    err
+                );
+                if *attempts < SLOT_ATTEMPTS {
+                    break;
+                }
+
+                // a slot failing for good must not stop the index
+                if let Err(err) = source.skip_slot(slot, err.message().to_string(), id).await {
+                    warn!("id {id}: failed to skip slot {slot}: {:?}", err);
+                    break;
+                }
+                warn!("id {id}: skipped slot {slot} after {SLOT_ATTEMPTS} attempts");
+                *attempts = 0;
+                indexed_slot = slot;
+            }
+        }
+    }
+
+    metrics::report_trace_index_progress(indexed_slot, latest_slot);
+}
+
+pub fn start_indexer(
+    source: DataSource,
+    start_slot: Option<u64>,
+    interval: Duration,
+) -> StopHandle {
+    let (stop_snd, stop_rcv) = tokio::sync::mpsc::channel::<()>(1);
+    StopHandle::new(
+        tokio::spawn(run_indexer(source, start_slot, interval, stop_rcv)),
+        stop_snd,
+    )
+}
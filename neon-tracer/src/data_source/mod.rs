@@ -1,20 +1,37 @@
+mod account_dump;
+pub mod call_index;
 mod neon_api;
 pub mod tracer_db;
 
 use {
     crate::{
-        api_client::{client::Client as NeonAPIClient, config::Config as NeonAPIConfig},
+        api_client::{
+            client::Client as NeonAPIClient,
+            config::{Config as NeonAPIConfig, MethodClass},
+        },
         data_source::neon_api::NeonAPIDataSource,
         service::{Error, Result},
+        tracers::{client_timeout, decode_revert_reason},
         types::BlockNumber,
     },
     arrayref::array_ref,
+    ethnum::U256,
     jsonrpsee::types::error::ErrorCode,
     neon_cli_lib::types::{IndexerDb, TracerDb},
-    std::sync::{atomic::AtomicU64, Arc},
+    serde::Serialize,
+    serde_json::value::RawValue,
+    std::{
+        io,
+        sync::{atomic::AtomicU64, Arc},
+        time::Duration,
+    },
     tracer_db::TracerDbExtention,
     tracing::{info, warn},
-    web3::{transports::Http, types::BlockId, Web3},
+    web3::{
+        transports::Http,
+        types::{BlockId, Bytes, Transaction, TransactionId, H160, H256},
+        Web3,
+    },
 };
 
 pub const ERR: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
@@ -23,6 +40,108 @@ pub const ERR: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
     Error::owned(code.code(), code.message(), None::<()>)
 };
 
+/// Internal error which keeps its description, for the failures of the trace indexer
+/// and the inconsistencies of Neon API worth reporting as is
+pub const INTERNAL_ERR: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
+    warn!("id {id:?}: error: {e}");
+    Error::owned(ErrorCode::InternalError.code(), e, None::<()>)
+};
+
+pub const INVALID_PARAMS: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
+    warn!("id {id:?}: invalid params: {e}");
+    Error::owned(ErrorCode::InvalidParams.code(), e, None::<()>)
+};
+
+/// Error code of the requests which did not finish in their time budget
+pub const TIMEOUT_ERROR_CODE: i32 = -32002;
+
+pub const TIMEOUT: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
+    warn!("id {id:?}: timeout: {e}");
+    Error::owned(TIMEOUT_ERROR_CODE, e, None::<()>)
+};
+
+/// Error code of the emulations stopped by the EVM step limit
+pub const STEP_LIMIT_ERROR_CODE: i32 = -32003;
+
+pub const STEP_LIMIT: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
+    warn!("id {id:?}: step limit: {e}");
+    Error::owned(STEP_LIMIT_ERROR_CODE, e, None::<()>)
+};
+
+/// Error code of the responses exceeding the size limit, the same as in jsonrpsee
+pub const RESPONSE_TOO_LARGE_ERROR_CODE: i32 = -32008;
+
+pub const RESPONSE_TOO_LARGE: fn(&str, id: u64) -> Error = |e: &str, id: u64| -> Error {
+    warn!("id {id:?}: {e}");
+    Error::owned(RESPONSE_TOO_LARGE_ERROR_CODE, e, None::<()>)
+};
+
+/// Bytes of the JSON-RPC envelope `{"jsonrpc":"2.0","result":...,"id":...}` around
+/// the result, with room for a client id of up to 64 bytes. The server limit applies to
+/// the whole envelope, so the result is limited to the rest.
+const RESPONSE_ENVELOPE_SIZE: usize = r#"{"jsonrpc":"2.0","result":,"id":}"#.len() + 64;
+
+/// Buffer of a serialized response, fails once the response exceeds the limit
+struct BoundedBuffer {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl io::Write for BoundedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() + buf.len() > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "response size limit exceeded",
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Error code of the reverted calls, the same as in geth and Neon proxy
+pub const REVERT_ERROR_CODE: i32 = 3;
+
+/// Error of a reverted call with the revert data passed through in `data`
+pub fn revert_error(output: &[u8]) -> Error {
+    let message = match decode_revert_reason(output) {
+        Some(reason) => format!("execution reverted: {reason}"),
+        None => "execution reverted".to_string(),
+    };
+    Error::owned(
+        REVERT_ERROR_CODE,
+        message,
+        Some(format!("0x{}", hex::encode(output))),
+    )
+}
+
+/// Checks that Neon API traced every transaction of the block at `slot`,
+/// the traces are matched with the transactions by position
+pub fn check_traced_count(slot: u64, transactions: usize, traced: usize, id: u64) -> Result<()> {
+    if transactions != traced {
+        return Err(INTERNAL_ERR(
+            &format!("block {slot} has {transactions} transactions, {traced} traced"),
+            id,
+        ));
+    }
+    Ok(())
+}
+
+/// Serializes `value` as long as it takes at most `limit` bytes
+fn serialize_bounded<T: Serialize>(value: &T, limit: usize) -> Option<Vec<u8>> {
+    let mut buffer = BoundedBuffer {
+        data: vec![],
+        limit,
+    };
+    serde_json::to_writer(&mut buffer, value).ok()?;
+    Some(buffer.data)
+}
+
 #[derive(Clone)]
 pub struct DataSource {
     tracer_db: TracerDb,
@@ -30,6 +149,7 @@ pub struct DataSource {
     web3: Arc<Web3<Http>>,
     pub neon_api: NeonAPIDataSource,
     pub request_id: Arc<AtomicU64>,
+    max_response_size: usize,
 }
 
 impl DataSource {
@@ -39,6 +159,7 @@ impl DataSource {
         web3: Arc<Web3<Http>>,
         neon_config: Arc<NeonAPIConfig>,
         neon_api_client: NeonAPIClient,
+        max_response_size: usize,
     ) -> Self {
         Self {
             tracer_db,
@@ -46,9 +167,77 @@ impl DataSource {
             web3,
             neon_api: NeonAPIDataSource::new(neon_config, neon_api_client),
             request_id: Arc::new(AtomicU64::new(1)),
+            max_response_size,
         }
     }
 
+    /// Serializes the response for jsonrpsee, which passes it through as is. The serialization
+    /// gives up as soon as the response exceeds the size limit less the envelope, so a huge
+    /// trace is never buffered in full and never reaches the server limit. Responses are logged
+    /// by their size only, their debug output is as large as they are.
+    pub fn limit_response_size<T: Serialize>(
+        &self,
+        method: &str,
+        result: Result<T>,
+        id: u64,
+    ) -> Result<Box<RawValue>> {
+        let response = result.map_err(|e| {
+            info!("id {id}: {method} => {e:?}");
+            e
+        })?;
+
+        let limit = self
+            .max_response_size
+            .saturating_sub(RESPONSE_ENVELOPE_SIZE);
+        let Some(data) = serialize_bounded(&response, limit) else {
+            return Err(RESPONSE_TOO_LARGE(
+                &format!(
+                    "response is too large, the limit is {} bytes",
+                    self.max_response_size
+                ),
+                id,
+            ));
+        };
+        info!("id {id}: {method} => {} bytes", data.len());
+
+        String::from_utf8(data)
+            .map_err(|e| e.to_string())
+            .and_then(|json| RawValue::from_string(json).map_err(|e| e.to_string()))
+            .map_err(|e| ERR(&format!("failed to serialize the response: {e}"), id))
+    }
+
+    pub fn default_steps(&self, class: MethodClass) -> u64 {
+        self.neon_api.config.step_limits.default_for(class)
+    }
+
+    /// Step limit of the request: the one requested by the client capped by the server maximum
+    /// or the default step limit of the method class
+    pub fn request_steps(&self, class: MethodClass, steps: Option<u64>) -> u64 {
+        steps.map_or(self.default_steps(class), |steps| {
+            steps.min(self.neon_api.config.step_limits.max)
+        })
+    }
+
+    pub fn default_timeout(&self, class: MethodClass) -> Duration {
+        self.neon_api.config.timeouts.default_for(class)
+    }
+
+    /// Timeout of the request: the geth `timeout` option capped by the server maximum
+    /// or the default timeout of the method class
+    pub fn request_timeout(
+        &self,
+        class: MethodClass,
+        timeout: Option<&str>,
+        id: u64,
+    ) -> Result<Duration> {
+        client_timeout(
+            timeout,
+            self.default_timeout(class),
+            self.neon_api.config.timeouts.max,
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))
+    }
+
     pub async fn get_block_number(&self, tag: BlockNumber, id: u64) -> Result<u64> {
         match tag {
             BlockNumber::Num(num) => Ok(num),
@@ -108,4 +297,114 @@ impl DataSource {
             }
         }
     }
+
+    pub async fn get_transaction(&self, hash: U256, id: u64) -> Result<Transaction> {
+        let hash = hash.to_be_bytes();
+        let hash_str = format!("0x{}", hex::encode(hash));
+
+        self.web3
+            .eth()
+            .transaction(TransactionId::Hash(web3::types::H256::from(hash)))
+            .await
+            .map_err(|e| {
+                ERR(
+                    &format!("failed to send eth_getTransactionByHash to proxy, {e:?}"),
+                    id,
+                )
+            })?
+            .ok_or_else(|| ERR(&format!("failed to obtain Transaction {hash_str:?}"), id))
+    }
+
+    /// Returns Neon transactions of the block in the execution order, as the indexer
+    /// recorded them for the active block at `slot`
+    pub async fn get_block_transactions(&self, slot: u64, id: u64) -> Result<Vec<Transaction>> {
+        let rows = self
+            .indexer_db
+            .client
+            .query(
+                "SELECT T.neon_sig, T.tx_idx, T.from_addr, T.to_addr, T.nonce, T.gas_price,
+                        T.gas_limit, T.value, T.calldata
+                 FROM neon_transactions T, solana_blocks B
+                 WHERE T.block_slot = $1 AND B.block_slot = T.block_slot AND B.is_active = true
+                 ORDER BY T.tx_idx",
+                &[&(slot as i64)],
+            )
+            .await
+            .map_err(|e| {
+                ERR(
+                    &format!("failed to read block {slot} transactions from indexer DB, {e:?}"),
+                    id,
+                )
+            })?;
+
+        let bytes =
+            |value: &str| hex::decode(value.trim_start_matches("0x")).map_err(|e| e.to_string());
+        let word = |value: &str| {
+            web3::types::U256::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|e| e.to_string())
+        };
+        let address = |value: &str| {
+            bytes(value).and_then(|b| {
+                <[u8; 20]>::try_from(b)
+                    .map(H160::from)
+                    .map_err(|_| format!("invalid address {value}"))
+            })
+        };
+
+        rows.iter()
+            .map(|row| -> std::result::Result<Transaction, String> {
+                let text =
+                    |column: &str| row.try_get::<_, String>(column).map_err(|e| e.to_string());
+                let hash = <[u8; 32]>::try_from(bytes(&text("neon_sig")?)?)
+                    .map_err(|_| "invalid transaction hash".to_string())?;
+                let index: i32 = row.try_get("tx_idx").map_err(|e| e.to_string())?;
+                let to: Option<String> = row.try_get("to_addr").map_err(|e| e.to_string())?;
+
+                Ok(Transaction {
+                    hash: H256::from(hash),
+                    nonce: word(&text("nonce")?)?,
+                    block_number: Some(slot.into()),
+                    transaction_index: Some((index as u64).into()),
+                    from: Some(address(&text("from_addr")?)?),
+                    to: to.as_deref().map(address).transpose()?,
+                    value: word(&text("value")?)?,
+                    gas_price: Some(word(&text("gas_price")?)?),
+                    gas: word(&text("gas_limit")?)?,
+                    input: Bytes(bytes(&text("calldata")?)?),
+                    ..Transaction::default()
+                })
+            })
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| {
+                INTERNAL_ERR(
+                    &format!("invalid transaction of block {slot} in the indexer DB: {e}"),
+                    id,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_with_envelope_fits_the_server_limit() {
+        let max_response_size = 1000;
+        let limit = max_response_size - RESPONSE_ENVELOPE_SIZE;
+
+        // a JSON string of `len` bytes including the quotes
+        let result = |len: usize| "x".repeat(len - 2);
+
+        let data = serialize_bounded(&result(limit), limit).unwrap();
+        assert_eq!(data.len(), limit);
+        assert!(serialize_bounded(&result(limit + 1), limit).is_none());
+
+        let id = format!("\"{}\"", "i".repeat(62));
+        let envelope = format!(
+            r#"{{"jsonrpc":"2.0","result":{},"id":{id}}}"#,
+            String::from_utf8(data).unwrap()
+        );
+        assert_eq!(envelope.len(), max_response_size);
+    }
 }
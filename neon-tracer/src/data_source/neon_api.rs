@@ -1,24 +1,27 @@
-use std::{sync::Arc, time::Duration};
+use std::{future::Future, sync::Arc, time::Duration};
 
-use crate::api_client::{client::Client, config::Config};
+use crate::api_client::{
+    client::Client, config::Config, errors::NeonAPIClientError, Result as ClientResult,
+};
 use crate::service::{Error, Result};
+use crate::tracers::is_reverted;
 use ethnum::U256;
 use evm_loader::evm::tracing::event_listener::trace::{TraceCallConfig, TraceConfig, TracedCall};
 use jsonrpsee::types::error::ErrorCode;
 use neon_cli_lib::{
-    commands::{get_storage_at::GetStorageAtReturn, trace::TraceBlockReturn},
+    commands::{
+        get_ether_account_data::GetEtherAccountDataReturn, get_storage_at::GetStorageAtReturn,
+        trace::TraceBlockReturn,
+    },
     types::Address,
 };
 
-use super::ERR;
-
-const NUM_STEPS_TO_EXECUTE: u64 = 500_000;
+use super::{revert_error, ERR, STEP_LIMIT, TIMEOUT};
 
 #[derive(Clone)]
 pub struct NeonAPIDataSource {
     pub config: Arc<Config>,
     pub api_client: Arc<Client>,
-    pub steps_to_execute: u64,
 }
 
 impl NeonAPIDataSource {
@@ -26,7 +29,34 @@ impl NeonAPIDataSource {
         NeonAPIDataSource {
             config,
             api_client: Arc::new(client),
-            steps_to_execute: NUM_STEPS_TO_EXECUTE,
+        }
+    }
+
+    /// Waits for the Neon API request at most `tout`, the request is dropped and so aborted
+    /// once the timeout expires. Only the timeouts are reported as the outer error.
+    async fn with_timeout<T>(
+        tout: &Duration,
+        id: u64,
+        request: impl Future<Output = ClientResult<T>>,
+    ) -> Result<ClientResult<T>> {
+        match tokio::time::timeout(*tout, request).await {
+            Ok(Err(e)) if e.is_timeout() => Err(TIMEOUT(&format!("execution timeout: {e}"), id)),
+            Ok(response) => Ok(response),
+            Err(_) => Err(TIMEOUT(
+                &format!("execution timeout: no response from Neon API in {tout:?}"),
+                id,
+            )),
+        }
+    }
+
+    fn emulation_error(e: NeonAPIClientError, steps: u64, id: u64) -> Error {
+        if e.is_step_limit() {
+            STEP_LIMIT(
+                &format!("execution stopped: step limit of {steps} steps reached"),
+                id,
+            )
+        } else {
+            Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>)
         }
     }
 
@@ -39,6 +69,7 @@ impl NeonAPIDataSource {
         value: Option<U256>,
         data: Option<Vec<u8>>,
         slot: u64,
+        steps: u64,
         tout: &Duration,
         id: u64,
     ) -> Result<String> {
@@ -47,39 +78,36 @@ impl NeonAPIDataSource {
         let slot = Some(slot);
         let token_mint = Some(self.config.clone().token_mint);
         let chain_id = Some(self.config.clone().chain_id);
-        let max_steps_to_execute = self.steps_to_execute;
+        let max_steps_to_execute = steps;
         let gas_limit = None;
         let cached_accounts = None;
         let solana_accounts = None;
 
-        let response = self
-            .api_client
-            .clone()
-            .emulate(
-                sender,
-                contract,
-                data,
-                value,
-                gas_limit,
-                max_steps_to_execute,
-                cached_accounts,
-                solana_accounts,
-                slot,
-                id,
-            )
-            .await
-            .map_err(|e| {
-                Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>)
-            })?;
-
-        Ok(format!(
-            "0x{}",
-            hex::encode(response.emulation_result.result)
-        ))
+        let api_client = self.api_client.clone();
+        let request = api_client.emulate(
+            sender,
+            contract,
+            data,
+            value,
+            gas_limit,
+            max_steps_to_execute,
+            cached_accounts,
+            solana_accounts,
+            slot,
+            id,
+        );
+        let response = Self::with_timeout(tout, id, request)
+            .await?
+            .map_err(|e| Self::emulation_error(e, steps, id))?;
+
+        let emulation_result = response.emulation_result;
+        if is_reverted(&emulation_result.exit_status) {
+            return Err(revert_error(&emulation_result.result));
+        }
+        Ok(format!("0x{}", hex::encode(emulation_result.result)))
     }
 
     #[allow(clippy::too_many_arguments)]
-    #[allow(unused)]
     pub async fn trace(
         &self,
         from: Option<Address>,
@@ -89,63 +117,63 @@ impl NeonAPIDataSource {
         gas_limit: Option<U256>,
         slot: u64,
         trace_call_config: Option<TraceCallConfig>,
+        steps: u64,
         tout: &Duration,
         id: u64,
     ) -> Result<TracedCall> {
-        self.api_client
-            .clone()
-            .trace(
-                from.unwrap_or_default(),
-                to,
-                data,
-                value,
-                gas_limit,
-                self.steps_to_execute,
-                None,
-                None,
-                Some(slot),
-                trace_call_config,
-                id,
-            )
-            .await
-            .map_err(|e| Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>))
+        let api_client = self.api_client.clone();
+        let request = api_client.trace(
+            from.unwrap_or_default(),
+            to,
+            data,
+            value,
+            gas_limit,
+            steps,
+            None,
+            None,
+            Some(slot),
+            trace_call_config,
+            id,
+        );
+        Self::with_timeout(tout, id, request)
+            .await?
+            .map_err(|e| Self::emulation_error(e, steps, id))
     }
 
-    #[allow(unused)]
     pub async fn trace_hash(
         &self,
         hash: U256,
         slot: u64,
         trace_config: Option<TraceConfig>,
+        steps: u64,
         tout: &Duration,
         id: u64,
     ) -> Result<TracedCall> {
         let hash = hash.to_be_bytes();
         let hash = format!("0x{}", hex::encode(hash));
 
-        self.api_client
-            .clone()
-            .trace_hash(self.steps_to_execute, None, None, hash, trace_config, id)
-            .await
-            .map_err(|e| Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>))
+        let api_client = self.api_client.clone();
+        let request = api_client.trace_hash(steps, None, None, hash, trace_config, id);
+        Self::with_timeout(tout, id, request)
+            .await?
+            .map_err(|e| Self::emulation_error(e, steps, id))
     }
 
-    #[allow(unused)]
     pub async fn trace_next_block(
         &self,
         slot: u64,
         trace_config: Option<TraceConfig>,
+        steps: u64,
         tout: &Duration,
         id: u64,
     ) -> Result<TraceBlockReturn> {
-        self.api_client
-            .clone()
-            .trace_next_block(self.steps_to_execute, None, None, slot, trace_config, id)
-            .await
-            .map_err(|e| Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>))
+        let api_client = self.api_client.clone();
+        let request = api_client.trace_next_block(steps, None, None, slot, trace_config, id);
+        Self::with_timeout(tout, id, request)
+            .await?
+            .map_err(|e| Self::emulation_error(e, steps, id))
     }
 
-    #[allow(unused)]
     pub async fn get_storage_at(
         &self,
         to: Address,
@@ -154,15 +182,27 @@ impl NeonAPIDataSource {
         tout: &Duration,
         id: u64,
     ) -> Result<U256> {
-        self.api_client
-            .clone()
-            .get_storage_at(to, index, Some(slot), id)
-            .await
+        let api_client = self.api_client.clone();
+        let request = api_client.get_storage_at(to, index, Some(slot), id);
+        Self::with_timeout(tout, id, request)
+            .await?
             .map(|GetStorageAtReturn(arr)| U256::from_be_bytes(arr))
             .map_err(|e| Error::owned(ErrorCode::InternalError.code(), e.to_string(), None::<()>))
     }
 
-    #[allow(unused)]
+    /// Balance, nonce and code of the account, `None` if Neon API does not report the account
+    pub async fn get_ether_account(
+        &self,
+        address: Address,
+        slot: u64,
+        tout: &Duration,
+        id: u64,
+    ) -> Result<Option<GetEtherAccountDataReturn>> {
+        let api_client = self.api_client.clone();
+        let request = api_client.get_ether_account_data(address, Some(slot), id);
+        Ok(Self::with_timeout(tout, id, request).await?.ok())
+    }
+
     pub async fn get_balance(
         &self,
         address: Address,
@@ -170,11 +210,9 @@ impl NeonAPIDataSource {
         tout: &Duration,
         id: u64,
     ) -> Result<U256> {
-        let response = self
-            .api_client
-            .clone()
-            .get_ether_account_data(address, Some(slot), id)
-            .await;
+        let api_client = self.api_client.clone();
+        let request = api_client.get_ether_account_data(address, Some(slot), id);
+        let response = Self::with_timeout(tout, id, request).await?;
 
         if response.is_err() {
             Ok(U256::default())
@@ -184,7 +222,6 @@ impl NeonAPIDataSource {
         }
     }
 
-    #[allow(unused)]
     pub async fn get_trx_count(
         &self,
         address: Address,
@@ -192,11 +229,9 @@ impl NeonAPIDataSource {
         tout: &Duration,
         id: u64,
     ) -> Result<U256> {
-        let response = self
-            .api_client
-            .clone()
-            .get_ether_account_data(address, Some(slot), id)
-            .await;
+        let api_client = self.api_client.clone();
+        let request = api_client.get_ether_account_data(address, Some(slot), id);
+        let response = Self::with_timeout(tout, id, request).await?;
 
         if response.is_err() {
             Ok(U256::default())
@@ -205,7 +240,6 @@ impl NeonAPIDataSource {
         }
     }
 
-    #[allow(unused)]
     pub async fn get_code(
         &self,
         address: Address,
@@ -213,11 +247,9 @@ impl NeonAPIDataSource {
         tout: &Duration,
         id: u64,
     ) -> Result<String> {
-        let response = self
-            .api_client
-            .clone()
-            .get_ether_account_data(address, Some(slot), id)
-            .await;
+        let api_client = self.api_client.clone();
+        let request = api_client.get_ether_account_data(address, Some(slot), id);
+        let response = Self::with_timeout(tout, id, request).await?;
 
         if response.is_err() {
             Ok(String::default())
@@ -3,9 +3,9 @@
 
 use {
     crate::{
-        data_source::DataSource,
+        data_source::{call_index::start_indexer, DataSource},
         metrics::start_monitoring,
-        service::{eip1898::EIP1898Server, geth::GethTraceServer},
+        service::{eip1898::EIP1898Server, geth::GethTraceServer, open_eth::OpenEthTraceServer},
     },
     jsonrpsee::server::{RpcModule, ServerBuilder},
     neon_cli_lib::types::{IndexerDb, TracerDb},
@@ -22,6 +22,7 @@ mod metrics;
 mod opcodes;
 mod service;
 mod stop_handle;
+mod tracers;
 mod types;
 
 fn init_logs() {
@@ -44,6 +45,7 @@ async fn run() {
     info!(?options, "starting");
 
     let server = ServerBuilder::default()
+        .max_response_body_size(u32::try_from(options.max_response_size).unwrap_or(u32::MAX))
         .build(options.addr.parse::<SocketAddr>().unwrap())
         .await
         .unwrap();
@@ -73,6 +75,7 @@ async fn run() {
         web3_client.clone(),
         neon_client_config,
         neon_client,
+        options.max_response_size,
     );
 
     let mut module = RpcModule::new(());
@@ -82,6 +85,9 @@ async fn run() {
     module
         .merge(GethTraceServer::into_rpc(source.clone()))
         .expect("GethTraceServer error");
+    module
+        .merge(OpenEthTraceServer::into_rpc(source.clone()))
+        .expect("OpenEthTraceServer error");
 
     let monitor_handle = start_monitoring(
         tracer_db.clone(),
@@ -90,6 +96,14 @@ async fn run() {
         options.metrics_port,
     );
 
+    let indexer_handle = options.trace_index_enabled.then(|| {
+        start_indexer(
+            source.clone(),
+            options.trace_index_start_slot,
+            std::time::Duration::from_secs(options.trace_index_interval_sec),
+        )
+    });
+
     let server_handle = server.start(module);
 
     let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
@@ -108,9 +122,24 @@ async fn run() {
         .stop()
         .expect("Failed to stop Monitoring")
         .await;
+    if let Some(indexer_handle) = indexer_handle {
+        let _ = indexer_handle
+            .stop()
+            .expect("Failed to stop Trace Indexer")
+            .await;
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    run().await;
+fn main() {
+    // JS tracers are executed by child processes of the tracer, see `tracers::run_js_worker`
+    if std::env::args().nth(1).as_deref() == Some(tracers::JS_WORKER_ARG) {
+        tracers::run_js_worker();
+        return;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the Tokio runtime")
+        .block_on(run());
 }
@@ -3,8 +3,8 @@ use {
     lazy_static::lazy_static,
     neon_cli_lib::types::TracerDb,
     prometheus::{
-        gather, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
-        TextEncoder,
+        gather, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts,
+        Registry, TextEncoder,
     },
     std::{net::Ipv4Addr, sync::Arc},
     tokio::{self, sync::mpsc::Receiver, time::Instant},
@@ -34,6 +34,16 @@ lazy_static! {
         "Difference between DB and Web3 slot number"
     ),)
     .expect("Failed create metric: neon_tracer_slot_difference");
+    pub static ref TRACE_INDEX_SLOT: IntGauge = IntGauge::new(
+        "neon_tracer_trace_index_slot",
+        "Last slot recorded into the trace index"
+    )
+    .expect("Failed create metric: neon_tracer_trace_index_slot");
+    pub static ref TRACE_INDEX_LAG: IntGauge = IntGauge::new(
+        "neon_tracer_trace_index_lag",
+        "Difference between the latest DB slot and the last indexed slot"
+    )
+    .expect("Failed create metric: neon_tracer_trace_index_lag");
 }
 
 async fn metrics_handler() -> Result<impl Reply, Rejection> {
@@ -98,6 +108,14 @@ fn register_metrics() {
     REGISTRY
         .register(Box::new(SLOT_DIFFERENCE.clone()))
         .expect("neon_tracer_slot_difference metric not registered");
+
+    REGISTRY
+        .register(Box::new(TRACE_INDEX_SLOT.clone()))
+        .expect("neon_tracer_trace_index_slot metric not registered");
+
+    REGISTRY
+        .register(Box::new(TRACE_INDEX_LAG.clone()))
+        .expect("neon_tracer_trace_index_lag metric not registered");
 }
 
 pub async fn run_monitoring(
@@ -180,3 +198,8 @@ pub fn report_request_finished(started: Instant, req_tag: &str, success: bool) {
         .with_label_values(&[req_tag])
         .observe(elapsed);
 }
+
+pub fn report_trace_index_progress(indexed_slot: u64, latest_slot: u64) {
+    TRACE_INDEX_SLOT.set(indexed_slot as i64);
+    TRACE_INDEX_LAG.set(latest_slot.saturating_sub(indexed_slot) as i64);
+}
@@ -1,5 +1,25 @@
 use std::collections::HashMap;
 
+pub const STOP: u8 = 0x00;
+pub const BALANCE: u8 = 0x31;
+pub const EXTCODESIZE: u8 = 0x3B;
+pub const EXTCODECOPY: u8 = 0x3C;
+pub const EXTCODEHASH: u8 = 0x3F;
+pub const SLOAD: u8 = 0x54;
+pub const SSTORE: u8 = 0x55;
+pub const LOG0: u8 = 0xA0;
+pub const LOG4: u8 = 0xA4;
+pub const CREATE: u8 = 0xF0;
+pub const CALL: u8 = 0xF1;
+pub const CALLCODE: u8 = 0xF2;
+pub const RETURN: u8 = 0xF3;
+pub const DELEGATECALL: u8 = 0xF4;
+pub const CREATE2: u8 = 0xF5;
+pub const STATICCALL: u8 = 0xFA;
+pub const REVERT: u8 = 0xFD;
+pub const INVALID: u8 = 0xFE;
+pub const SELFDESTRUCT: u8 = 0xFF;
+
 lazy_static::lazy_static! {
     pub static ref OPCODES: HashMap<u8, &'static str> = HashMap::from([
         (0x00, "STOP"),
@@ -153,3 +173,16 @@ pub fn opcode_name(instruction: u8) -> &'static str {
         .get(&instruction)
         .map_or_else(|| "<invalid>", |v| *v)
 }
+
+/// Opcodes with the name matching the pattern, a trailing `*` matches any suffix
+pub fn opcodes_matching(pattern: &str) -> Vec<u8> {
+    let pattern = pattern.to_ascii_uppercase();
+    OPCODES
+        .iter()
+        .filter(|(_, name)| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => **name == pattern,
+        })
+        .map(|(instruction, _)| *instruction)
+        .collect()
+}
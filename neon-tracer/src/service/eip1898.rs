@@ -1,12 +1,18 @@
 use {
     crate::{
-        data_source::DataSource,
+        api_client::config::MethodClass,
+        data_source::{revert_error, DataSource, INVALID_PARAMS},
         metrics,
         service::Result,
-        types::{geth::TransactionArgs, BlockNumber},
+        tracers::is_reverted,
+        types::{
+            geth::{trace_call_config, BlockOverrides, StateOverrides, TransactionArgs},
+            BlockNumber,
+        },
     },
     async_trait::async_trait,
     ethnum::U256,
+    evm_loader::evm::tracing::event_listener::trace::TraceConfig,
     jsonrpsee::proc_macros::rpc,
     neon_cli_lib::types::Address,
     std::sync::atomic::Ordering,
@@ -17,7 +23,13 @@ use {
 #[async_trait]
 pub trait EIP1898 {
     #[method(name = "eth_call")]
-    async fn eth_call(&self, object: TransactionArgs, tag: BlockNumber) -> Result<String>;
+    async fn eth_call(
+        &self,
+        object: TransactionArgs,
+        tag: BlockNumber,
+        state_overrides: Option<StateOverrides>,
+        block_overrides: Option<BlockOverrides>,
+    ) -> Result<String>;
     #[method(name = "eth_getStorageAt")]
     async fn eth_get_storage_at(
         &self,
@@ -35,13 +47,19 @@ pub trait EIP1898 {
 
 #[async_trait]
 impl EIP1898Server for DataSource {
-    async fn eth_call(&self, o: TransactionArgs, tag: BlockNumber) -> Result<String> {
+    async fn eth_call(
+        &self,
+        o: TransactionArgs,
+        tag: BlockNumber,
+        state_overrides: Option<StateOverrides>,
+        block_overrides: Option<BlockOverrides>,
+    ) -> Result<String> {
         let started = metrics::report_incoming_request("eth_call");
 
         let data = o.input.map(|a| a.0);
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!(
-            "id {:?}: eth_call(caller={:?}, contract={:?}, gas={:?}, gasPrice={:?}, data={:?}, value={:?})",
+            "id {:?}: eth_call(caller={:?}, contract={:?}, gas={:?}, gasPrice={:?}, data={:?}, value={:?}, stateOverrides={:?}, blockOverrides={:?})",
             id,
             o.from,
             o.to,
@@ -49,14 +67,55 @@ impl EIP1898Server for DataSource {
             o.gas_price,
             data.as_ref().map(hex::encode),
             o.value,
+            state_overrides,
+            block_overrides,
         );
 
-        let tout = std::time::Duration::new(10, 0);
+        let tout = self.default_timeout(MethodClass::Call);
+        let steps = self.default_steps(MethodClass::Call);
         let slot = self.get_block_number(tag, id).await?;
-        let result = self
-            .neon_api
-            .emulate(o.from, o.to, o.value, data, slot, &tout, id)
-            .await;
+        let result = if state_overrides.is_none() && block_overrides.is_none() {
+            self.neon_api
+                .emulate(o.from, o.to, o.value, data, slot, steps, &tout, id)
+                .await
+        } else {
+            // emulation doesn't take overrides, the call is traced without the per-step data
+            let trace_config = TraceConfig {
+                enable_memory: false,
+                disable_storage: true,
+                disable_stack: true,
+                enable_return_data: false,
+                ..TraceConfig::default()
+            };
+            let config = trace_call_config(
+                trace_config,
+                block_overrides.as_ref(),
+                state_overrides.as_ref(),
+            )
+            .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
+
+            self.neon_api
+                .trace(
+                    o.from,
+                    o.to,
+                    o.value,
+                    data,
+                    o.gas,
+                    slot,
+                    Some(config),
+                    steps,
+                    &tout,
+                    id,
+                )
+                .await
+                .and_then(|traced_call| {
+                    if is_reverted(&traced_call.exit_status) {
+                        Err(revert_error(&traced_call.result))
+                    } else {
+                        Ok(format!("0x{}", hex::encode(traced_call.result)))
+                    }
+                })
+        };
         info!("id {:?}: eth_call => {:?}", id, result);
         metrics::report_request_finished(started, "eth_call", result.is_ok());
 
@@ -77,7 +136,7 @@ impl EIP1898Server for DataSource {
             id, address, index, tag
         );
 
-        let tout = std::time::Duration::new(10, 0);
+        let tout = self.default_timeout(MethodClass::Call);
         let slot = self.get_block_number(tag, id).await?;
         let value = self
             .neon_api
@@ -95,7 +154,7 @@ impl EIP1898Server for DataSource {
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!("id {:?}: eth_getBalance({:?}, {:?})", id, address, tag);
 
-        let tout = std::time::Duration::new(10, 0);
+        let tout = self.default_timeout(MethodClass::Call);
         let slot = self.get_block_number(tag, id).await?;
         let balance = self.neon_api.get_balance(address, slot, &tout, id).await;
         info!("id {:?}: eth_getBalance => {:?}", id, balance);
@@ -110,7 +169,7 @@ impl EIP1898Server for DataSource {
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!("id {:?}: eth_getCode({:?}, {:?})", id, address, tag);
 
-        let tout = std::time::Duration::new(10, 0);
+        let tout = self.default_timeout(MethodClass::Call);
         let slot = self.get_block_number(tag, id).await?;
         let code = self.neon_api.get_code(address, slot, &tout, id).await;
         info!("id {:?}, eth_getCode => {:?}", id, code);
@@ -128,7 +187,7 @@ impl EIP1898Server for DataSource {
             id, address, tag
         );
 
-        let tout = std::time::Duration::new(10, 0);
+        let tout = self.default_timeout(MethodClass::Call);
         let slot = self.get_block_number(tag, id).await?;
         let count = self.neon_api.get_trx_count(address, slot, &tout, id).await;
         info!("id {:?}: eth_getTransactionCount => {:?}", id, count);
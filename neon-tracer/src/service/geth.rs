@@ -1,21 +1,36 @@
-use std::sync::atomic::Ordering;
+use std::{collections::BTreeMap, sync::atomic::Ordering, time::Instant};
 
 use async_trait::async_trait;
 use ethnum::U256;
-use evm_loader::evm::tracing::event_listener::trace::{TraceCallConfig, TraceConfig};
+use evm_loader::evm::tracing::event_listener::trace::{TraceConfig, TracedCall};
+use futures::{stream, StreamExt};
 use jsonrpsee::proc_macros::rpc;
-use tracing::info;
+use neon_cli_lib::types::Address;
+use serde_json::value::RawValue;
+use tracing::{info, warn};
+use web3::{signing::keccak256, types::Transaction};
 
 use crate::{
-    data_source::{DataSource, ERR},
+    api_client::config::MethodClass,
+    data_source::{check_traced_count, DataSource, ERR, INTERNAL_ERR, INVALID_PARAMS},
     metrics,
     service::Result,
+    tracers::{
+        parity_trace_config, storage_writes, StateChanges, StateOverlay, Tracer, TxContext, Word,
+    },
     types::{
-        geth::{ExecutionResult, Trace, TransactionArgs},
+        geth::{
+            trace_call_config, GethTraceCallConfig, GethTraceConfig, StorageEntry,
+            StorageRangeResult, TransactionArgs, TxTraceResult,
+        },
         BlockNumber,
     },
 };
 
+/// Number of storage slots `debug_storageRangeAt` reads at once
+const STORAGE_READ_CONCURRENCY: usize = 16;
+
+/// Responses are passed to jsonrpsee already serialized by `DataSource::limit_response_size`
 #[rpc(server)]
 pub trait GethTrace {
     #[method(name = "debug_traceCall")]
@@ -23,18 +38,44 @@ pub trait GethTrace {
         &self,
         a: TransactionArgs,
         b: BlockNumber,
-        o: Option<TraceCallConfig>,
-    ) -> Result<Trace>;
+        o: Option<GethTraceCallConfig>,
+    ) -> Result<Box<RawValue>>;
     #[method(name = "debug_traceTransaction")]
-    async fn trace_transaction(&self, t: U256, o: Option<TraceConfig>) -> Result<Trace>;
+    async fn trace_transaction(&self, t: U256, o: Option<GethTraceConfig>)
+        -> Result<Box<RawValue>>;
     #[method(name = "debug_traceBlockByNumber")]
     async fn trace_block_by_number(
         &self,
         b: BlockNumber,
-        o: Option<TraceConfig>,
-    ) -> Result<Vec<Trace>>;
+        o: Option<GethTraceConfig>,
+    ) -> Result<Box<RawValue>>;
     #[method(name = "debug_traceBlockByHash")]
-    async fn trace_block_by_hash(&self, bh: U256, o: Option<TraceConfig>) -> Result<Vec<Trace>>;
+    async fn trace_block_by_hash(
+        &self,
+        bh: U256,
+        o: Option<GethTraceConfig>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "debug_storageRangeAt")]
+    async fn storage_range_at(
+        &self,
+        block_hash: U256,
+        tx_index: usize,
+        address: Address,
+        start_key: U256,
+        max_result: usize,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "debug_getModifiedAccountsByNumber")]
+    async fn get_modified_accounts_by_number(
+        &self,
+        start_num: BlockNumber,
+        end_num: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "debug_getModifiedAccountsByHash")]
+    async fn get_modified_accounts_by_hash(
+        &self,
+        start_hash: U256,
+        end_hash: Option<U256>,
+    ) -> Result<Box<RawValue>>;
 }
 
 #[async_trait]
@@ -43,10 +84,11 @@ impl GethTraceServer for DataSource {
         &self,
         a: TransactionArgs,
         tag: BlockNumber,
-        o: Option<TraceCallConfig>,
-    ) -> Result<Trace> {
+        o: Option<GethTraceCallConfig>,
+    ) -> Result<Box<RawValue>> {
         let started = metrics::report_incoming_request("debug_traceCall");
 
+        let ctx = TxContext::from(&a);
         let data = a.input.map(|v| v.0);
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!(
@@ -61,7 +103,23 @@ impl GethTraceServer for DataSource {
             o,
         );
 
-        let tout = std::time::Duration::new(10, 0);
+        let o = o.unwrap_or_default();
+        let tracer = Tracer::new(
+            &o.trace_config,
+            o.tracer_config.as_ref(),
+            &self.neon_api.config,
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
+        let upstream_config = trace_call_config(
+            tracer.upstream_config(&o.trace_config),
+            o.block_overrides.as_ref(),
+            o.state_overrides.as_ref(),
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
+
+        let tout =
+            self.request_timeout(MethodClass::Trace, o.trace_config.timeout.as_deref(), id)?;
+        let steps = self.request_steps(MethodClass::Trace, o.max_steps_to_execute);
         let slot = self.get_block_number(tag, id).await?;
         let result = self
             .neon_api
@@ -72,34 +130,60 @@ impl GethTraceServer for DataSource {
                 data,
                 a.gas,
                 slot,
-                o.clone(),
+                Some(upstream_config),
+                steps,
                 &tout,
                 id,
             )
             .await;
 
-        let result = result.map(|trace_call| {
-            let o = o.unwrap_or_default();
-            let response = Trace::Logs(ExecutionResult::new(trace_call, &o.trace_config));
-            info!("id {:?}: debug_traceCall => {:?}", id, response);
-            response
-        });
+        let result = match result {
+            Ok(trace_call) => tracer
+                .trace(
+                    trace_call,
+                    &o.trace_config,
+                    &TxContext { slot, ..ctx },
+                    &StateOverlay::default(),
+                    self,
+                    id,
+                )
+                .await
+                .map_err(|e| e.into_rpc_error(id)),
+            Err(e) => Err(e),
+        };
+        let result = self.limit_response_size("debug_traceCall", result, id);
         metrics::report_request_finished(started, "debug_traceCall", result.is_ok());
 
         result
     }
 
-    async fn trace_transaction(&self, hash: U256, o: Option<TraceConfig>) -> Result<Trace> {
+    async fn trace_transaction(
+        &self,
+        hash: U256,
+        o: Option<GethTraceConfig>,
+    ) -> Result<Box<RawValue>> {
         let started = metrics::report_incoming_request("debug_traceTransaction");
 
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!(
-            "id {:?}: debug_traceTransaction (hash={:?})",
+            "id {:?}: debug_traceTransaction (hash={:?}, config={:?})",
             id,
-            hash.to_string()
+            hash.to_string(),
+            o,
         );
 
-        let tout = std::time::Duration::new(10, 0);
+        let o = o.unwrap_or_default();
+        let tracer = Tracer::new(
+            &o.trace_config,
+            o.tracer_config.as_ref(),
+            &self.neon_api.config,
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
+
+        let tout =
+            self.request_timeout(MethodClass::Trace, o.trace_config.timeout.as_deref(), id)?;
+        let deadline = Instant::now() + tout;
+        let steps = self.request_steps(MethodClass::Trace, o.max_steps_to_execute);
         let h = hash.to_be_bytes();
         let slot = self
             .indexer_db
@@ -107,17 +191,44 @@ impl GethTraceServer for DataSource {
             .await
             .map_err(|e| ERR(&format!("get_slot error: {e}"), id))?;
 
+        let ctx = if tracer.needs_transaction() {
+            TxContext::from(&self.get_transaction(hash, id).await?)
+        } else {
+            TxContext::default()
+        };
+
         let result = self
             .neon_api
-            .trace_hash(hash, slot, o.clone(), &tout, id)
+            .trace_hash(
+                hash,
+                slot,
+                Some(tracer.upstream_config(&o.trace_config)),
+                steps,
+                &tout,
+                id,
+            )
             .await;
 
-        let result = result.map(|trace_call| {
-            let o = o.unwrap_or_default();
-            let response = Trace::Logs(ExecutionResult::new(trace_call, &o));
-            info!("id {:?}: debug_traceTransaction => {:?}", id, response);
-            response
-        });
+        let result = match result {
+            Ok(trace_call) => {
+                let overlay = match ctx.position {
+                    Some(position) if position > 0 && tracer.needs_block_state() => {
+                        self.block_overlay(ctx.slot + 1, position, deadline, id)
+                            .await
+                    }
+                    _ => Ok(StateOverlay::default()),
+                };
+                match overlay {
+                    Ok(overlay) => tracer
+                        .trace(trace_call, &o.trace_config, &ctx, &overlay, self, id)
+                        .await
+                        .map_err(|e| e.into_rpc_error(id)),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        let result = self.limit_response_size("debug_traceTransaction", result, id);
         metrics::report_request_finished(started, "debug_traceTransaction", result.is_ok());
 
         result
@@ -126,72 +237,460 @@ impl GethTraceServer for DataSource {
     async fn trace_block_by_number(
         &self,
         tag: BlockNumber,
-        o: Option<TraceConfig>,
-    ) -> Result<Vec<Trace>> {
+        o: Option<GethTraceConfig>,
+    ) -> Result<Box<RawValue>> {
         let started = metrics::report_incoming_request("debug_traceBlockByNumber");
 
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!("id {id}: debug_traceBlockByNumber (tag={tag:?}, config={o:?})");
 
-        let tout = std::time::Duration::new(10, 0);
-        let slot = self.get_block_number(tag, id).await?;
-        if slot == 0 {
-            return Err(ERR("Genesis block is not traceable", id));
-        }
-
-        let result = self
-            .neon_api
-            .trace_next_block(slot - 1, o.clone(), &tout, id)
-            .await;
+        let o = o.unwrap_or_default();
+        let tracer = Tracer::new(
+            &o.trace_config,
+            o.tracer_config.as_ref(),
+            &self.neon_api.config,
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
 
-        let result = result.map(|trace_calls| {
-            let o = o.unwrap_or_default();
-            let response = trace_calls
-                .0
-                .into_iter()
-                .map(|trace_call| Trace::Logs(ExecutionResult::new(trace_call, &o)))
-                .collect();
-            info!("debug_traceBlockByNumber => {:?}", response);
-            response
-        });
+        let slot = self.get_block_number(tag, id).await?;
+        let result = self.trace_block(slot, &tracer, &o, id).await;
+        let result = self.limit_response_size("debug_traceBlockByNumber", result, id);
         metrics::report_request_finished(started, "debug_traceBlockByNumber", result.is_ok());
 
         result
     }
 
-    async fn trace_block_by_hash(&self, hash: U256, o: Option<TraceConfig>) -> Result<Vec<Trace>> {
+    async fn trace_block_by_hash(
+        &self,
+        hash: U256,
+        o: Option<GethTraceConfig>,
+    ) -> Result<Box<RawValue>> {
         let started = metrics::report_incoming_request("debug_traceBlockByHash");
 
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         info!("id {id}: debug_traceBlockByHash (hash={hash}, config={o:?})");
 
-        let tout = std::time::Duration::new(10, 0);
+        let o = o.unwrap_or_default();
+        let tracer = Tracer::new(
+            &o.trace_config,
+            o.tracer_config.as_ref(),
+            &self.neon_api.config,
+        )
+        .map_err(|e| INVALID_PARAMS(&e.to_string(), id))?;
+
         let hash = hash.to_be_bytes();
         let slot = self
             .indexer_db
             .get_slot_by_block_hash(&hash)
             .await
             .map_err(|e| ERR(&format!("get_slot_by_block_hash error: {e}"), id))?;
+        let result = self.trace_block(slot, &tracer, &o, id).await;
+        let result = self.limit_response_size("debug_traceBlockByHash", result, id);
+        metrics::report_request_finished(started, "debug_traceBlockByHash", result.is_ok());
+
+        result
+    }
+
+    async fn storage_range_at(
+        &self,
+        block_hash: U256,
+        tx_index: usize,
+        address: Address,
+        start_key: U256,
+        max_result: usize,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("debug_storageRangeAt");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!(
+            "id {id}: debug_storageRangeAt (block_hash={block_hash}, tx_index={tx_index}, address={address:?}, start_key={start_key}, max_result={max_result})"
+        );
+
+        let result: Result<StorageRangeResult> = async {
+            let hash = block_hash.to_be_bytes();
+            let slot = self
+                .indexer_db
+                .get_slot_by_block_hash(&hash)
+                .await
+                .map_err(|e| ERR(&format!("get_slot_by_block_hash error: {e}"), id))?;
+            if slot == 0 {
+                return Err(ERR("Genesis block is not traceable", id));
+            }
+
+            self.storage_range(slot, tx_index, address, start_key, max_result, id)
+                .await
+        }
+        .await;
+        let result = self.limit_response_size("debug_storageRangeAt", result, id);
+        metrics::report_request_finished(started, "debug_storageRangeAt", result.is_ok());
+
+        result
+    }
+
+    async fn get_modified_accounts_by_number(
+        &self,
+        start_num: BlockNumber,
+        end_num: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("debug_getModifiedAccountsByNumber");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: debug_getModifiedAccountsByNumber (start={start_num:?}, end={end_num:?})");
+
+        let result: Result<Vec<Address>> = async {
+            let start_slot = self.get_block_number(start_num, id).await?;
+            let end_slot = match end_num {
+                Some(end_num) => Some(self.get_block_number(end_num, id).await?),
+                None => None,
+            };
+
+            self.modified_accounts(start_slot, end_slot, id).await
+        }
+        .await;
+        let result = self.limit_response_size("debug_getModifiedAccountsByNumber", result, id);
+        metrics::report_request_finished(
+            started,
+            "debug_getModifiedAccountsByNumber",
+            result.is_ok(),
+        );
+
+        result
+    }
+
+    async fn get_modified_accounts_by_hash(
+        &self,
+        start_hash: U256,
+        end_hash: Option<U256>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("debug_getModifiedAccountsByHash");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: debug_getModifiedAccountsByHash (start={start_hash}, end={end_hash:?})");
+
+        let result: Result<Vec<Address>> = async {
+            let get_slot = |hash: U256| async move {
+                self.indexer_db
+                    .get_slot_by_block_hash(&hash.to_be_bytes())
+                    .await
+                    .map_err(|e| ERR(&format!("get_slot_by_block_hash error: {e}"), id))
+            };
+            let start_slot = get_slot(start_hash).await?;
+            let end_slot = match end_hash {
+                Some(end_hash) => Some(get_slot(end_hash).await?),
+                None => None,
+            };
+
+            self.modified_accounts(start_slot, end_slot, id).await
+        }
+        .await;
+        let result = self.limit_response_size("debug_getModifiedAccountsByHash", result, id);
+        metrics::report_request_finished(
+            started,
+            "debug_getModifiedAccountsByHash",
+            result.is_ok(),
+        );
+
+        result
+    }
+}
+
+impl DataSource {
+    /// Accounts modified by the block at `start` if `end` is not set,
+    /// otherwise by the blocks after `start` up to `end` inclusive, the same as in geth
+    async fn modified_accounts(
+        &self,
+        start: u64,
+        end: Option<u64>,
+        id: u64,
+    ) -> Result<Vec<Address>> {
+        let (from_slot, to_slot) = match end {
+            None => (start, start),
+            Some(end) if start < end => (start + 1, end),
+            Some(end) => {
+                return Err(INVALID_PARAMS(
+                    &format!("start block ({start}) must be less than end block ({end})"),
+                    id,
+                ))
+            }
+        };
+
+        self.get_modified_accounts(from_slot, to_slot)
+            .await
+            .map_err(|e| ERR(&format!("failed to read the account dumps: {e:?}"), id))
+    }
+
+    /// Reads the storage of `address` in the state before the transaction `tx_index`
+    /// of the block at `slot`. The slot keys are taken from the account dumps at the end
+    /// of the parent slot and from the writes of the preceding transactions of the block.
+    async fn storage_range(
+        &self,
+        slot: u64,
+        tx_index: usize,
+        address: Address,
+        start_key: U256,
+        max_result: usize,
+        id: u64,
+    ) -> Result<StorageRangeResult> {
+        // values written by the preceding transactions of the block
+        let mut written = BTreeMap::new();
+        if tx_index > 0 {
+            let tout = self.default_timeout(MethodClass::BlockTrace);
+            let steps = self.default_steps(MethodClass::BlockTrace);
+            let transactions = self.get_block_transactions(slot, id).await?;
+            if tx_index >= transactions.len() {
+                return Err(INVALID_PARAMS(
+                    &format!("transaction index {tx_index} out of range for block {slot}"),
+                    id,
+                ));
+            }
+            let traced_calls = self
+                .neon_api
+                .trace_next_block(slot - 1, Some(parity_trace_config()), steps, &tout, id)
+                .await?;
+            check_traced_count(slot, transactions.len(), traced_calls.0.len(), id)?;
+
+            for (tx, traced_call) in transactions.iter().zip(&traced_calls.0).take(tx_index) {
+                let writes = storage_writes(traced_call, &TxContext::from(tx));
+                written.extend(
+                    writes
+                        .into_iter()
+                        .filter(|((written_address, _), _)| *written_address == address)
+                        .map(|((_, key), value)| (key, value)),
+                );
+            }
+        }
+
+        let hash = |key: &U256| U256::from_be_bytes(keccak256(&key.to_be_bytes()));
+        let mut keys: BTreeMap<U256, U256> = self
+            .get_storage_keys(&address, slot - 1)
+            .await
+            .map_err(|e| ERR(&format!("failed to read the account dumps: {e:?}"), id))?
+            .into_iter()
+            .chain(written.keys().copied())
+            .map(|key| (hash(&key), key))
+            .collect();
+        keys.retain(|hashed_key, _| *hashed_key >= start_key);
+
+        // the values are read ahead concurrently, the reads in flight are dropped
+        // once `max_result` slots are collected
+        let tout = &self.default_timeout(MethodClass::Call);
+        let written = &written;
+        let mut values = stream::iter(keys)
+            .map(|(hashed_key, key)| async move {
+                let value: Result<U256> = match written.get(&key) {
+                    Some(value) => Ok(*value),
+                    None => {
+                        self.neon_api
+                            .get_storage_at(address, key, slot - 1, tout, id)
+                            .await
+                    }
+                };
+                value.map(|value| (hashed_key, key, value))
+            })
+            .buffered(STORAGE_READ_CONCURRENCY);
+
+        let mut result = StorageRangeResult::default();
+        while let Some(entry) = values.next().await {
+            let (hashed_key, key, value) = entry?;
+            // cleared slots are not part of the state, the same as in the geth trie
+            if value == U256::ZERO {
+                continue;
+            }
+            if result.storage.len() == max_result {
+                result.next_key = Some(Word(hashed_key));
+                break;
+            }
+
+            result.storage.insert(
+                Word(hashed_key),
+                StorageEntry {
+                    key: Some(Word(key)),
+                    value: Word(value),
+                },
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// Changes made by the transactions of the block at `slot` preceding the one at `position`,
+    /// the block is traced in the time left until `deadline` of the request
+    pub async fn block_overlay(
+        &self,
+        slot: u64,
+        position: usize,
+        deadline: Instant,
+        id: u64,
+    ) -> Result<StateOverlay> {
+        let tout = deadline.saturating_duration_since(Instant::now());
+        let steps = self.default_steps(MethodClass::BlockTrace);
+        let transactions = self.get_block_transactions(slot, id).await?;
+        let traced_calls = self
+            .neon_api
+            .trace_next_block(slot - 1, Some(parity_trace_config()), steps, &tout, id)
+            .await?;
+        check_traced_count(slot, transactions.len(), traced_calls.0.len(), id)?;
+
+        let mut overlay = StateOverlay::default();
+        for (tx, traced_call) in transactions.iter().zip(&traced_calls.0).take(position) {
+            let changes =
+                StateChanges::with_overlay(traced_call, &TxContext::from(tx), &overlay, self, id)
+                    .await
+                    .map_err(|e| e.into_rpc_error(id))?;
+            overlay.update(&changes);
+        }
+
+        Ok(overlay)
+    }
+
+    async fn trace_block(
+        &self,
+        slot: u64,
+        tracer: &Tracer,
+        o: &GethTraceConfig,
+        id: u64,
+    ) -> Result<Vec<TxTraceResult>> {
         if slot == 0 {
             return Err(ERR("Genesis block is not traceable", id));
         }
-        let result = self
-            .neon_api
-            .trace_next_block(slot - 1, o.clone(), &tout, id)
-            .await;
 
-        let result = result.map(|trace_calls| {
-            let o = o.unwrap_or_default();
-            let response = trace_calls
-                .0
-                .into_iter()
-                .map(|trace_call| Trace::Logs(ExecutionResult::new(trace_call, &o)))
-                .collect();
-            info!("debug_traceBlockByHash => {:?}", response);
-            response
-        });
-        metrics::report_request_finished(started, "debug_traceBlockByHash", result.is_ok());
+        let tout = self.request_timeout(
+            MethodClass::BlockTrace,
+            o.trace_config.timeout.as_deref(),
+            id,
+        )?;
+        // the whole request, including the per-transaction requests, is bounded by `tout`
+        let deadline = Instant::now() + tout;
+        let steps = self.request_steps(MethodClass::BlockTrace, o.max_steps_to_execute);
+        let transactions = self.get_block_transactions(slot, id).await?;
+        let upstream_config = tracer.upstream_config(&o.trace_config);
 
-        result
+        let concurrency = self.neon_api.config.block_trace_concurrency;
+        let trace_calls: Vec<Result<TracedCall>> = if concurrency > 0 {
+            self.trace_transactions(
+                &transactions,
+                slot,
+                &upstream_config,
+                steps,
+                deadline,
+                concurrency,
+                id,
+            )
+            .await
+        } else {
+            match self
+                .neon_api
+                .trace_next_block(slot - 1, Some(upstream_config.clone()), steps, &tout, id)
+                .await
+            {
+                // the transactions Neon API did not trace get an error of their own
+                Ok(trace_calls) => {
+                    let mut trace_calls = trace_calls.0.into_iter();
+                    transactions
+                        .iter()
+                        .map(|tx| {
+                            trace_calls.next().ok_or_else(|| {
+                                INTERNAL_ERR(
+                                    &format!("transaction {:?} is not traced by Neon API", tx.hash),
+                                    id,
+                                )
+                            })
+                        })
+                        .collect()
+                }
+                // the transactions are traced one by one so that a failed one doesn't hide the rest
+                Err(e) => {
+                    warn!("id {id}: failed to trace block {slot}, tracing its transactions one by one: {e:?}");
+                    self.trace_transactions(
+                        &transactions,
+                        slot,
+                        &upstream_config,
+                        steps,
+                        deadline,
+                        1,
+                        id,
+                    )
+                    .await
+                }
+            }
+        };
+
+        // every transaction is executed on top of the changes of the preceding ones,
+        // the overlay is dropped once the changes of a transaction are not known
+        let needs_state = tracer.needs_block_state();
+        let mut overlay = Some(StateOverlay::default());
+
+        let mut response = Vec::with_capacity(trace_calls.len());
+        for (idx, (tx, trace_call)) in transactions.iter().zip(trace_calls).enumerate() {
+            let trace = match trace_call {
+                Err(e) => {
+                    overlay = None;
+                    Err(e.message().to_string())
+                }
+                Ok(trace_call) => {
+                    let ctx = TxContext::from(tx);
+                    if !needs_state {
+                        tracer
+                            .trace(
+                                trace_call,
+                                &o.trace_config,
+                                &ctx,
+                                &StateOverlay::default(),
+                                self,
+                                id,
+                            )
+                            .await
+                            .map_err(|e| e.message())
+                    } else if let Some(mut state) = overlay.take() {
+                        let changes =
+                            StateChanges::with_overlay(&trace_call, &ctx, &state, self, id).await;
+                        let trace = tracer
+                            .trace(trace_call, &o.trace_config, &ctx, &state, self, id)
+                            .await
+                            .map_err(|e| e.message());
+                        if let Ok(changes) = changes {
+                            state.update(&changes);
+                            overlay = Some(state);
+                        }
+                        trace
+                    } else {
+                        Err(format!(
+                            "state changes of the transactions before {idx} are not known"
+                        ))
+                    }
+                }
+            };
+            let tx_hash = Some(Word(U256::from_be_bytes(tx.hash.0)));
+            response.push(TxTraceResult::new(tx_hash, trace));
+        }
+
+        Ok(response)
+    }
+
+    /// Traces the block transactions by separate requests, at most `concurrency` at once,
+    /// the results are in the block order. Every request gets the time left until `deadline`,
+    /// the requests started after it fail with the timeout error.
+    #[allow(clippy::too_many_arguments)]
+    async fn trace_transactions(
+        &self,
+        transactions: &[Transaction],
+        slot: u64,
+        config: &TraceConfig,
+        steps: u64,
+        deadline: Instant,
+        concurrency: usize,
+        id: u64,
+    ) -> Vec<Result<TracedCall>> {
+        stream::iter(transactions)
+            .map(|tx| async move {
+                let hash = U256::from_be_bytes(tx.hash.0);
+                let tout = deadline.saturating_duration_since(Instant::now());
+                self.neon_api
+                    .trace_hash(hash, slot, Some(config.clone()), steps, &tout, id)
+                    .await
+            })
+            .buffered(concurrency)
+            .collect()
+            .await
     }
 }
@@ -1,5 +1,6 @@
 pub mod eip1898;
 pub mod geth;
+pub mod open_eth;
 
 pub type Error = jsonrpsee::types::error::ErrorObjectOwned;
 pub type Result<T> = std::result::Result<T, Error>;
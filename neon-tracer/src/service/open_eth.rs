@@ -0,0 +1,587 @@
+use std::{sync::atomic::Ordering, time::Instant};
+
+use async_trait::async_trait;
+use ethnum::U256;
+use evm_loader::{evm::tracing::event_listener::trace::TracedCall, types::hexbytes::HexBytes};
+use jsonrpsee::proc_macros::rpc;
+use serde_json::value::RawValue;
+use tracing::info;
+use web3::types::Transaction;
+
+use crate::{
+    api_client::config::MethodClass,
+    data_source::{
+        call_index::{address_to_string, CallTraceFilter},
+        check_traced_count, DataSource, ERR, INVALID_PARAMS,
+    },
+    metrics,
+    service::Result,
+    tracers::{
+        localized_traces, parity_call_config, parity_trace_config, LocalizedTrace, StateChanges,
+        StateOverlay, TraceResults, TraceResultsWithTransactionHash, TxContext, TxLocation, Word,
+    },
+    types::{
+        geth::TransactionArgs, raw_transaction::RawTransaction, BlockNumber, TraceFilter,
+        TraceIndex, TraceType,
+    },
+};
+
+/// Maximal number of traces returned by one `trace_filter` request, the rest is paged with `after`
+const MAX_TRACE_FILTER_COUNT: u64 = 10_000;
+
+/// Every trace is serialized once, by `DataSource::limit_response_size` bounding its size
+#[rpc(server)]
+pub trait OpenEthTrace {
+    #[method(name = "trace_replayTransaction")]
+    async fn replay_transaction(
+        &self,
+        hash: U256,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "trace_replayBlockTransactions")]
+    async fn replay_block_transactions(
+        &self,
+        tag: BlockNumber,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "trace_call")]
+    async fn call(
+        &self,
+        a: TransactionArgs,
+        trace_types: Vec<TraceType>,
+        tag: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "trace_callMany")]
+    async fn call_many(
+        &self,
+        calls: Vec<(TransactionArgs, Vec<TraceType>)>,
+        tag: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "trace_rawTransaction")]
+    async fn raw_transaction(
+        &self,
+        raw_tx: HexBytes,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>>;
+    #[method(name = "trace_transaction")]
+    async fn transaction(&self, hash: U256) -> Result<Box<RawValue>>;
+    #[method(name = "trace_get")]
+    async fn get(&self, hash: U256, indices: Vec<TraceIndex>) -> Result<Box<RawValue>>;
+    #[method(name = "trace_block")]
+    async fn block(&self, tag: BlockNumber) -> Result<Box<RawValue>>;
+    #[method(name = "trace_filter")]
+    async fn filter(&self, filter: TraceFilter) -> Result<Box<RawValue>>;
+}
+
+#[async_trait]
+impl OpenEthTraceServer for DataSource {
+    async fn replay_transaction(
+        &self,
+        hash: U256,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_replayTransaction");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_replayTransaction (hash={hash}, trace_types={trace_types:?})");
+
+        let tout = self.default_timeout(MethodClass::Trace);
+        let deadline = Instant::now() + tout;
+        let steps = self.default_steps(MethodClass::Trace);
+        let h = hash.to_be_bytes();
+        let slot = self
+            .indexer_db
+            .get_slot(&h)
+            .await
+            .map_err(|e| ERR(&format!("get_slot error: {e}"), id))?;
+        let ctx = TxContext::from(&self.get_transaction(hash, id).await?);
+
+        let result = self
+            .neon_api
+            .trace_hash(hash, slot, Some(parity_trace_config()), steps, &tout, id)
+            .await;
+
+        let result = match result {
+            Ok(trace_call) => {
+                // stateDiff is taken against the changes of the preceding block transactions
+                let overlay = match ctx.position {
+                    Some(position)
+                        if position > 0 && trace_types.contains(&TraceType::StateDiff) =>
+                    {
+                        self.block_overlay(ctx.slot + 1, position, deadline, id)
+                            .await
+                    }
+                    _ => Ok(StateOverlay::default()),
+                };
+                match overlay {
+                    Ok(overlay) => {
+                        TraceResults::new(&trace_call, &trace_types, &ctx, &overlay, self, id)
+                            .await
+                            .map_err(|e| e.into_rpc_error(id))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        let result = self.limit_response_size("trace_replayTransaction", result, id);
+        metrics::report_request_finished(started, "trace_replayTransaction", result.is_ok());
+
+        result
+    }
+
+    async fn replay_block_transactions(
+        &self,
+        tag: BlockNumber,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_replayBlockTransactions");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_replayBlockTransactions (tag={tag:?}, trace_types={trace_types:?})");
+
+        let result: Result<Vec<TraceResultsWithTransactionHash>> = async {
+            let slot = self.get_block_number(tag, id).await?;
+
+            // every transaction is executed on top of the changes of the previous ones
+            let mut overlay = StateOverlay::default();
+            let mut results = vec![];
+            for (tx, traced_call) in self.replay_block(slot, id).await? {
+                let ctx = TxContext::from(&tx);
+                let changes = if trace_types.contains(&TraceType::StateDiff) {
+                    let changes =
+                        StateChanges::with_overlay(&traced_call, &ctx, &overlay, self, id)
+                            .await
+                            .map_err(|e| e.into_rpc_error(id))?;
+                    overlay.update(&changes);
+                    Some(changes)
+                } else {
+                    None
+                };
+
+                results.push(TraceResultsWithTransactionHash {
+                    results: TraceResults::build(
+                        &traced_call,
+                        &trace_types,
+                        &ctx,
+                        changes.as_ref(),
+                    ),
+                    transaction_hash: Word(U256::from_be_bytes(tx.hash.0)),
+                });
+            }
+
+            Ok(results)
+        }
+        .await;
+        let result = self.limit_response_size("trace_replayBlockTransactions", result, id);
+        metrics::report_request_finished(started, "trace_replayBlockTransactions", result.is_ok());
+
+        result
+    }
+
+    async fn call(
+        &self,
+        a: TransactionArgs,
+        trace_types: Vec<TraceType>,
+        tag: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_call");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_call (args={a:?}, trace_types={trace_types:?}, tag={tag:?})");
+
+        let result: Result<TraceResults> = async {
+            let slot = self.get_block_number(tag.unwrap_or_default(), id).await?;
+            self.trace_call_on(
+                a,
+                &trace_types,
+                slot,
+                &mut StateOverlay::default(),
+                false,
+                id,
+            )
+            .await
+        }
+        .await;
+        let result = self.limit_response_size("trace_call", result, id);
+        metrics::report_request_finished(started, "trace_call", result.is_ok());
+
+        result
+    }
+
+    async fn call_many(
+        &self,
+        calls: Vec<(TransactionArgs, Vec<TraceType>)>,
+        tag: Option<BlockNumber>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_callMany");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_callMany (calls={calls:?}, tag={tag:?})");
+
+        let result: Result<Vec<TraceResults>> = async {
+            let slot = self.get_block_number(tag.unwrap_or_default(), id).await?;
+
+            let count = calls.len();
+            let mut overlay = StateOverlay::default();
+            let mut results = Vec::with_capacity(count);
+            for (idx, (a, trace_types)) in calls.into_iter().enumerate() {
+                let is_last = idx + 1 == count;
+                let trace = self
+                    .trace_call_on(a, &trace_types, slot, &mut overlay, !is_last, id)
+                    .await?;
+                results.push(trace);
+            }
+
+            Ok(results)
+        }
+        .await;
+        let result = self.limit_response_size("trace_callMany", result, id);
+        metrics::report_request_finished(started, "trace_callMany", result.is_ok());
+
+        result
+    }
+
+    async fn raw_transaction(
+        &self,
+        raw_tx: HexBytes,
+        trace_types: Vec<TraceType>,
+    ) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_rawTransaction");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!(
+            "id {id}: trace_rawTransaction (raw_tx={}, trace_types={trace_types:?})",
+            hex::encode(&raw_tx.0)
+        );
+
+        let result: Result<TraceResults> = async {
+            let tx = RawTransaction::decode(&raw_tx.0)
+                .map_err(|e| INVALID_PARAMS(&format!("Invalid raw transaction: {e}"), id))?;
+            // legacy transactions signed without a chain id are replayable on any network
+            let chain_id = self.neon_api.config.chain_id;
+            match tx.chain_id {
+                Some(tx_chain_id) if tx_chain_id == chain_id => {}
+                Some(tx_chain_id) => {
+                    return Err(INVALID_PARAMS(
+                        &format!("Invalid chain id {tx_chain_id}, expected {chain_id}"),
+                        id,
+                    ))
+                }
+                None => {
+                    return Err(INVALID_PARAMS(
+                        "Transactions without EIP-155 chain id are not supported",
+                        id,
+                    ))
+                }
+            }
+
+            let tout = self.default_timeout(MethodClass::Trace);
+            let steps = self.default_steps(MethodClass::Trace);
+            let slot = self.get_block_number(BlockNumber::Latest, id).await?;
+            let ctx = TxContext {
+                slot,
+                ..TxContext::from(&tx)
+            };
+
+            let traced_call = self
+                .neon_api
+                .trace(
+                    Some(tx.from),
+                    tx.to,
+                    Some(tx.value),
+                    Some(tx.input),
+                    Some(U256::from(tx.gas)),
+                    slot,
+                    Some(
+                        parity_call_config(&StateOverlay::default())
+                            .map_err(|e| e.into_rpc_error(id))?,
+                    ),
+                    steps,
+                    &tout,
+                    id,
+                )
+                .await?;
+
+            TraceResults::new(
+                &traced_call,
+                &trace_types,
+                &ctx,
+                &StateOverlay::default(),
+                self,
+                id,
+            )
+            .await
+            .map_err(|e| e.into_rpc_error(id))
+        }
+        .await;
+        let result = self.limit_response_size("trace_rawTransaction", result, id);
+        metrics::report_request_finished(started, "trace_rawTransaction", result.is_ok());
+
+        result
+    }
+
+    async fn transaction(&self, hash: U256) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_transaction");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_transaction (hash={hash})");
+
+        let result = self.transaction_traces(hash, id).await;
+        let result = self.limit_response_size("trace_transaction", result, id);
+        metrics::report_request_finished(started, "trace_transaction", result.is_ok());
+
+        result
+    }
+
+    async fn get(&self, hash: U256, indices: Vec<TraceIndex>) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_get");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_get (hash={hash}, indices={indices:?})");
+
+        // indices are the trace address of the call, the same as in OpenEthereum
+        let trace_address: Vec<usize> = indices.iter().map(|index| index.0).collect();
+        let result = self.transaction_traces(hash, id).await.map(|traces| {
+            traces
+                .into_iter()
+                .find(|trace| trace.trace.trace_address == trace_address)
+        });
+        let result = self.limit_response_size("trace_get", result, id);
+        metrics::report_request_finished(started, "trace_get", result.is_ok());
+
+        result
+    }
+
+    async fn block(&self, tag: BlockNumber) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_block");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_block (tag={tag:?})");
+
+        let result: Result<Vec<LocalizedTrace>> = async {
+            let slot = self.get_block_number(tag, id).await?;
+            let traces = self
+                .replay_block(slot, id)
+                .await?
+                .iter()
+                .flat_map(|(tx, traced_call)| {
+                    localized_traces(traced_call, &TxContext::from(tx), &TxLocation::from(tx))
+                })
+                .collect();
+            Ok(traces)
+        }
+        .await;
+        let result = self.limit_response_size("trace_block", result, id);
+        metrics::report_request_finished(started, "trace_block", result.is_ok());
+
+        result
+    }
+
+    async fn filter(&self, filter: TraceFilter) -> Result<Box<RawValue>> {
+        let started = metrics::report_incoming_request("trace_filter");
+
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+        info!("id {id}: trace_filter (filter={filter:?})");
+
+        let result: Result<Vec<Box<RawValue>>> = async {
+            let filter = self.call_trace_filter(filter, id).await?;
+            let traces = self
+                .filter_call_traces(&filter)
+                .await
+                .map_err(|e| ERR(&format!("failed to read the trace index: {e:?}"), id))?;
+
+            traces
+                .into_iter()
+                .map(|trace| {
+                    RawValue::from_string(trace)
+                        .map_err(|e| ERR(&format!("invalid indexed trace: {e}"), id))
+                })
+                .collect()
+        }
+        .await;
+        let result = self.limit_response_size("trace_filter", result, id);
+        metrics::report_request_finished(started, "trace_filter", result.is_ok());
+
+        result
+    }
+}
+
+impl DataSource {
+    /// Resolves the block range of `filter` against the trace index
+    async fn call_trace_filter(&self, filter: TraceFilter, id: u64) -> Result<CallTraceFilter> {
+        let indexed_slot = self
+            .get_indexed_slot()
+            .await
+            .map_err(|e| ERR(&format!("failed to read the trace index: {e:?}"), id))?
+            .ok_or_else(|| INVALID_PARAMS("trace index is empty", id))?;
+        let first_slot = self
+            .get_first_indexed_slot()
+            .await
+            .map_err(|e| ERR(&format!("failed to read the trace index: {e:?}"), id))?
+            .unwrap_or(indexed_slot);
+
+        // the blocks before the first indexed one have no traces recorded
+        let from_slot = match filter.from_block {
+            Some(tag) => {
+                let slot = self.get_block_number(tag, id).await?;
+                if slot < first_slot {
+                    return Err(INVALID_PARAMS(
+                        &format!(
+                            "block {slot} is not indexed, the first indexed block is {first_slot}"
+                        ),
+                        id,
+                    ));
+                }
+                slot
+            }
+            None => first_slot,
+        };
+        // the latest block is the latest indexed one, explicit blocks must be indexed already
+        let to_slot = match filter.to_block {
+            None | Some(BlockNumber::Latest) => indexed_slot,
+            Some(tag) => {
+                let slot = self.get_block_number(tag, id).await?;
+                if slot > indexed_slot {
+                    return Err(INVALID_PARAMS(
+                        &format!("block {slot} is not indexed yet, the last indexed block is {indexed_slot}"),
+                        id,
+                    ));
+                }
+                slot
+            }
+        };
+        if from_slot > to_slot {
+            return Err(INVALID_PARAMS(
+                &format!("fromBlock {from_slot} is after toBlock {to_slot}"),
+                id,
+            ));
+        }
+
+        let failed_slot = self
+            .get_failed_slot(from_slot, to_slot)
+            .await
+            .map_err(|e| ERR(&format!("failed to read the trace index: {e:?}"), id))?;
+        if let Some(slot) = failed_slot {
+            return Err(ERR(&format!("block {slot} failed to be indexed"), id));
+        }
+
+        let limit = filter.count.unwrap_or(MAX_TRACE_FILTER_COUNT);
+        if limit > MAX_TRACE_FILTER_COUNT {
+            return Err(INVALID_PARAMS(
+                &format!("count {limit} exceeds the maximum of {MAX_TRACE_FILTER_COUNT}"),
+                id,
+            ));
+        }
+
+        let addresses = |addresses: Option<Vec<_>>| {
+            addresses
+                .unwrap_or_default()
+                .iter()
+                .map(address_to_string)
+                .collect()
+        };
+        Ok(CallTraceFilter {
+            from_slot,
+            to_slot,
+            from_addresses: addresses(filter.from_address),
+            to_addresses: addresses(filter.to_address),
+            offset: filter.after.unwrap_or(0),
+            limit,
+        })
+    }
+
+    async fn transaction_traces(&self, hash: U256, id: u64) -> Result<Vec<LocalizedTrace>> {
+        let tout = self.default_timeout(MethodClass::Trace);
+        let steps = self.default_steps(MethodClass::Trace);
+        let h = hash.to_be_bytes();
+        let slot = self
+            .indexer_db
+            .get_slot(&h)
+            .await
+            .map_err(|e| ERR(&format!("get_slot error: {e}"), id))?;
+        let tx = self.get_transaction(hash, id).await?;
+
+        let traced_call = self
+            .neon_api
+            .trace_hash(hash, slot, Some(parity_trace_config()), steps, &tout, id)
+            .await?;
+
+        Ok(localized_traces(
+            &traced_call,
+            &TxContext::from(&tx),
+            &TxLocation::from(&tx),
+        ))
+    }
+
+    /// Replays the transactions of the block, returns them along with their traces
+    async fn replay_block(&self, slot: u64, id: u64) -> Result<Vec<(Transaction, TracedCall)>> {
+        if slot == 0 {
+            return Err(ERR("Genesis block is not traceable", id));
+        }
+
+        let tout = self.default_timeout(MethodClass::BlockTrace);
+        let steps = self.default_steps(MethodClass::BlockTrace);
+        let transactions = self.get_block_transactions(slot, id).await?;
+        let traced_calls = self
+            .neon_api
+            .trace_next_block(slot - 1, Some(parity_trace_config()), steps, &tout, id)
+            .await?;
+
+        check_traced_count(slot, transactions.len(), traced_calls.0.len(), id)?;
+
+        Ok(transactions.into_iter().zip(traced_calls.0).collect())
+    }
+
+    /// Traces the call on top of the changes in `overlay`,
+    /// puts the changes made by the call into `overlay` if `update` is set
+    async fn trace_call_on(
+        &self,
+        a: TransactionArgs,
+        trace_types: &[TraceType],
+        slot: u64,
+        overlay: &mut StateOverlay,
+        update: bool,
+        id: u64,
+    ) -> Result<TraceResults> {
+        let tout = self.default_timeout(MethodClass::Trace);
+        let steps = self.default_steps(MethodClass::Trace);
+        let ctx = TxContext {
+            slot,
+            ..TxContext::from(&a)
+        };
+        let config = parity_call_config(overlay).map_err(|e| e.into_rpc_error(id))?;
+
+        let traced_call = self
+            .neon_api
+            .trace(
+                a.from,
+                a.to,
+                a.value,
+                a.input.map(|v| v.0),
+                a.gas,
+                slot,
+                Some(config),
+                steps,
+                &tout,
+                id,
+            )
+            .await?;
+
+        let changes = if update || trace_types.contains(&TraceType::StateDiff) {
+            let changes = StateChanges::with_overlay(&traced_call, &ctx, overlay, self, id)
+                .await
+                .map_err(|e| e.into_rpc_error(id))?;
+            Some(changes)
+        } else {
+            None
+        };
+        let results = TraceResults::build(&traced_call, trace_types, &ctx, changes.as_ref());
+
+        if let Some(changes) = changes.filter(|_| update) {
+            overlay.update(&changes);
+        }
+
+        Ok(results)
+    }
+}
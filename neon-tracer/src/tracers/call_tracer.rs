@@ -0,0 +1,254 @@
+use std::convert::Infallible;
+
+use ethnum::U256;
+use evm_loader::evm::tracing::event_listener::trace::{
+    FullTraceData, TracedCall, VMOperation, VMTrace,
+};
+use evm_loader::types::hexbytes::HexBytes;
+use neon_cli_lib::types::Address;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    decode_revert_reason, exit_status_error, frame_error, frame_output, gas_cost, memory_slice,
+    pushed_word, stack_item, walk, CallKind, CallSite, TraceVisitor, TxContext, Visit,
+};
+use crate::opcodes;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CallTracerConfig {
+    /// Do not trace the nested calls
+    pub only_top_call: bool,
+    /// Include the logs emitted by every call
+    pub with_log: bool,
+}
+
+/// `CallFrame` is a single call of the geth `callTracer` call tree
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub kind: CallKind,
+    pub from: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<U256>,
+    pub gas: U256,
+    pub gas_used: U256,
+    pub input: HexBytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<HexBytes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<CallLog>,
+}
+
+/// Log emitted by a call, reported when `withLog` is set
+#[derive(Serialize, Debug, Clone)]
+pub struct CallLog {
+    pub address: Address,
+    pub topics: Vec<HexBytes>,
+    pub data: HexBytes,
+    /// Number of nested calls made by the frame before the log was emitted
+    pub position: U256,
+}
+
+impl CallLog {
+    fn decode(
+        operation: &VMOperation,
+        data: &FullTraceData,
+        address: Address,
+        position: usize,
+    ) -> Option<Self> {
+        if !(opcodes::LOG0..=opcodes::LOG4).contains(&operation.instruction) {
+            return None;
+        }
+
+        let topics_count = usize::from(operation.instruction - opcodes::LOG0);
+        let topics = (0..topics_count)
+            .map(|n| stack_item(data, n + 2).map(|topic| topic.to_be_bytes().to_vec().into()))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            address,
+            topics,
+            data: memory_slice(data, stack_item(data, 0)?, stack_item(data, 1)?).into(),
+            position: U256::try_from(position).unwrap_or_default(),
+        })
+    }
+}
+
+impl CallFrame {
+    pub fn new(traced_call: &TracedCall, ctx: &TxContext, config: &CallTracerConfig) -> Self {
+        let kind = if ctx.to.is_some() {
+            CallKind::Call
+        } else {
+            CallKind::Create
+        };
+        let to = ctx.to.or_else(|| {
+            ctx.nonce
+                .map(|nonce| Address::from_create(&ctx.from, nonce))
+        });
+        let gas_used = U256::from(traced_call.used_gas);
+
+        let frame = Self {
+            kind,
+            from: ctx.from,
+            to,
+            value: Some(ctx.value),
+            gas: ctx.gas.map_or(gas_used, U256::from),
+            gas_used,
+            input: ctx.input.clone().into(),
+            output: None,
+            error: exit_status_error(&traced_call.exit_status),
+            revert_reason: None,
+            calls: vec![],
+            logs: vec![],
+        };
+
+        let mut builder = CallTreeBuilder {
+            config,
+            frames: vec![(frame, to.unwrap_or_default())],
+        };
+        walk(traced_call, &mut builder).unwrap_or_else(|never| match never {});
+        let (mut frame, _) = builder
+            .frames
+            .pop()
+            .expect("the top level frame is never closed");
+
+        if !traced_call.result.is_empty() {
+            frame.output = Some(traced_call.result.clone().into());
+        }
+        frame.finish();
+
+        frame
+    }
+
+    fn finish(&mut self) {
+        if self.error.is_none() {
+            return;
+        }
+
+        self.revert_reason = self
+            .output
+            .as_ref()
+            .and_then(|output| decode_revert_reason(&output.0));
+        self.clear_logs();
+    }
+
+    /// Logs of the failed call and all of its nested calls are discarded
+    fn clear_logs(&mut self) {
+        self.logs.clear();
+        self.calls.iter_mut().for_each(Self::clear_logs);
+    }
+}
+
+/// Builds the tree of the nested calls, a frame is open until its call returns
+struct CallTreeBuilder<'a> {
+    config: &'a CallTracerConfig,
+    /// Open frames with the contract the code of which they execute, the top level call first
+    frames: Vec<(CallFrame, Address)>,
+}
+
+impl<'t> TraceVisitor<'t> for CallTreeBuilder<'_> {
+    type Error = Infallible;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Infallible> {
+        let (frame, address) = self.current();
+        if self.config.with_log {
+            let log = visit
+                .data
+                .and_then(|d| CallLog::decode(visit.operation, d, *address, frame.calls.len()));
+            frame.logs.extend(log);
+        }
+
+        // calls of the precompiles and the accounts without code have no nested steps
+        if visit.sub.is_none() && !self.config.only_top_call {
+            if let Some(site) = visit.call_site() {
+                let (child, _) = self.child(site, None);
+                self.close(child, visit);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self, visit: &Visit<'t>) -> Result<bool, Infallible> {
+        let site = match visit.call_site() {
+            Some(site) if !self.config.only_top_call => site,
+            _ => return Ok(false),
+        };
+
+        let child = self.child(site, visit.sub);
+        self.frames.push(child);
+
+        Ok(true)
+    }
+
+    fn exit(&mut self, visit: &Visit<'t>, last: Option<&Visit<'t>>) -> Result<(), Infallible> {
+        let (mut child, _) = self.frames.pop().expect("the nested call frame is open");
+        if let Some(last) = last {
+            child.output = last
+                .data
+                .and_then(|d| frame_output(last.operation, d))
+                .filter(|output| !output.is_empty())
+                .map(Into::into);
+            child.error = frame_error(last.operation);
+        }
+        self.close(child, visit);
+
+        Ok(())
+    }
+}
+
+impl CallTreeBuilder<'_> {
+    fn current(&mut self) -> &mut (CallFrame, Address) {
+        self.frames
+            .last_mut()
+            .expect("the top level frame is never closed")
+    }
+
+    /// Opens the frame of a nested call made by the current frame
+    fn child(&mut self, site: CallSite, sub: Option<&VMTrace>) -> (CallFrame, Address) {
+        let parent = self.current().1;
+        // DELEGATECALL and CALLCODE execute the callee code in the context of the caller
+        let address = match site.kind {
+            CallKind::DelegateCall | CallKind::CallCode => parent,
+            _ => site.to.unwrap_or_default(),
+        };
+        let gas_used = sub.map_or(U256::ZERO, gas_cost);
+
+        let frame = CallFrame {
+            kind: site.kind,
+            from: parent,
+            to: site.to,
+            value: site.value,
+            gas: site.gas.unwrap_or(gas_used),
+            gas_used,
+            input: site.input.into(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+            logs: vec![],
+        };
+
+        (frame, address)
+    }
+
+    /// Adds the finished nested call made at `visit` to the current frame
+    fn close(&mut self, mut child: CallFrame, visit: &Visit) {
+        if child.error.is_none() && pushed_word(visit.operation) == Some(U256::ZERO) {
+            child.error = Some("execution failed".to_string());
+        }
+        child.finish();
+
+        self.current().0.calls.push(child);
+    }
+}
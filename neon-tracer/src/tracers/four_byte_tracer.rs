@@ -0,0 +1,57 @@
+use std::{collections::BTreeMap, convert::Infallible};
+
+use evm_loader::evm::tracing::event_listener::trace::TracedCall;
+use neon_cli_lib::types::Address;
+
+use super::{walk, CallKind, TraceVisitor, TxContext, Visit};
+
+/// Counts the `0x<selector>-<calldata size>` pairs of the top level call
+/// and every nested CALL, CALLCODE, DELEGATECALL and STATICCALL
+pub fn four_byte_trace(traced_call: &TracedCall, ctx: &TxContext) -> BTreeMap<String, u64> {
+    let mut counter = SelectorCounter::default();
+
+    if ctx.to.is_some() {
+        counter.count(&ctx.input);
+    }
+    walk(traced_call, &mut counter).unwrap_or_else(|never| match never {});
+
+    counter.selectors
+}
+
+#[derive(Default)]
+struct SelectorCounter {
+    selectors: BTreeMap<String, u64>,
+}
+
+impl<'t> TraceVisitor<'t> for SelectorCounter {
+    type Error = Infallible;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Infallible> {
+        if let Some(site) = visit
+            .call_site()
+            .filter(|site| !matches!(site.kind, CallKind::Create | CallKind::Create2))
+            .filter(|site| !site.to.map_or(false, is_precompile))
+        {
+            self.count(&site.input);
+        }
+
+        Ok(())
+    }
+}
+
+impl SelectorCounter {
+    fn count(&mut self, input: &[u8]) {
+        if input.len() < 4 {
+            return;
+        }
+
+        let key = format!("0x{}-{}", hex::encode(&input[..4]), input.len() - 4);
+        *self.selectors.entry(key).or_default() += 1;
+    }
+}
+
+/// Calls to the precompiled contracts are not counted, the same way geth does
+fn is_precompile(address: Address) -> bool {
+    let (prefix, last) = address.0.split_at(19);
+    prefix.iter().all(|byte| *byte == 0) && (1..=9).contains(&last[0])
+}
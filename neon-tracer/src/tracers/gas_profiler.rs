@@ -0,0 +1,297 @@
+use std::{collections::BTreeMap, convert::Infallible};
+
+use evm_loader::evm::tracing::event_listener::trace::TracedCall;
+use neon_cli_lib::types::Address;
+use serde::Serialize;
+
+use super::{walk, CallKind, TraceVisitor, TxContext, Visit};
+use crate::{opcodes::opcode_name, types::geth::Step};
+
+/// Result of `gasProfiler`: the gas cost of the steps aggregated by call frame,
+/// contract, function selector and opcode, and the folded stacks for flamegraph tools
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GasProfile {
+    /// Sum of the step costs, without the intrinsic gas and the refund
+    pub gas: u64,
+    pub call: ProfileFrame,
+    /// Gas of the steps executing the contract code
+    pub by_address: BTreeMap<Address, u64>,
+    /// Gas of the own steps of the called functions, `fallback` for the calls without a selector
+    pub by_selector: BTreeMap<String, u64>,
+    pub by_opcode: BTreeMap<&'static str, OpcodeGas>,
+    /// `<frame>;<frame>;<opcode> <gas>` lines, a frame is `<contract>:<selector>`
+    pub folded: String,
+}
+
+/// Call frame with the gas of its own steps and of the nested calls
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileFrame {
+    #[serde(rename = "type")]
+    pub kind: CallKind,
+    /// Contract the code of which is executed
+    pub address: Address,
+    pub selector: String,
+    pub self_gas: u64,
+    pub total_gas: u64,
+    /// Gas of the frame own steps by opcode
+    pub opcodes: BTreeMap<&'static str, u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<ProfileFrame>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct OpcodeGas {
+    pub count: u64,
+    pub gas: u64,
+}
+
+impl ProfileFrame {
+    fn new(kind: CallKind, address: Address, input: &[u8]) -> Self {
+        let selector = match kind {
+            CallKind::Create | CallKind::Create2 => "constructor".to_string(),
+            _ if input.len() < 4 => "fallback".to_string(),
+            _ => format!("0x{}", hex::encode(&input[..4])),
+        };
+
+        Self {
+            kind,
+            address,
+            selector,
+            self_gas: 0,
+            total_gas: 0,
+            opcodes: BTreeMap::new(),
+            calls: vec![],
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("0x{}:{}", hex::encode(self.address.0), self.selector)
+    }
+}
+
+/// Open call frame and its folded stack prefix
+struct OpenFrame {
+    frame: ProfileFrame,
+    path: String,
+}
+
+impl GasProfile {
+    pub fn new(traced_call: &TracedCall, ctx: &TxContext) -> Self {
+        let (kind, to) = match ctx.to {
+            Some(to) => (CallKind::Call, to),
+            None => (
+                CallKind::Create,
+                ctx.nonce
+                    .map(|nonce| Address::from_create(&ctx.from, nonce))
+                    .unwrap_or_default(),
+            ),
+        };
+        let steps = Step::collect(traced_call, ctx);
+        let mut profiler = GasProfiler::new(&steps, ProfileFrame::new(kind, to, &ctx.input));
+        walk(traced_call, &mut profiler).unwrap_or_else(|never| match never {});
+
+        profiler.finish()
+    }
+}
+
+struct GasProfiler<'a> {
+    /// Steps of the trace with the gas costs, indexed the same way as `full_trace_data`
+    steps: &'a [Step],
+    open: Vec<OpenFrame>,
+    gas: u64,
+    by_address: BTreeMap<Address, u64>,
+    by_selector: BTreeMap<String, u64>,
+    by_opcode: BTreeMap<&'static str, OpcodeGas>,
+    folded: BTreeMap<String, u64>,
+}
+
+impl<'t> TraceVisitor<'t> for GasProfiler<'_> {
+    type Error = Infallible;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Infallible> {
+        self.record(visit.step);
+
+        Ok(())
+    }
+
+    fn enter(&mut self, visit: &Visit<'t>) -> Result<bool, Infallible> {
+        let frame = match visit.call_site() {
+            Some(site) => ProfileFrame::new(site.kind, site.to.unwrap_or_default(), &site.input),
+            None => ProfileFrame::new(CallKind::Call, Address::default(), &[]),
+        };
+        self.enter_frame(frame);
+
+        Ok(true)
+    }
+
+    fn exit(&mut self, _visit: &Visit<'t>, _last: Option<&Visit<'t>>) -> Result<(), Infallible> {
+        self.close();
+
+        Ok(())
+    }
+}
+
+impl<'a> GasProfiler<'a> {
+    fn new(steps: &'a [Step], root: ProfileFrame) -> Self {
+        Self {
+            steps,
+            open: vec![OpenFrame {
+                path: root.label(),
+                frame: root,
+            }],
+            gas: 0,
+            by_address: BTreeMap::new(),
+            by_selector: BTreeMap::new(),
+            by_opcode: BTreeMap::new(),
+            folded: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the gas cost of the step to the innermost frame
+    fn record(&mut self, idx: usize) {
+        let Some(step) = self.steps.get(idx) else {
+            return;
+        };
+
+        let op_name = opcode_name(step.instruction);
+        let current = self.current();
+        current.frame.self_gas += step.gas_cost;
+        *current.frame.opcodes.entry(op_name).or_default() += step.gas_cost;
+        let stack = format!("{};{op_name}", current.path);
+        let address = current.frame.address;
+
+        *self.folded.entry(stack).or_default() += step.gas_cost;
+        *self.by_address.entry(address).or_default() += step.gas_cost;
+        let opcode = self.by_opcode.entry(op_name).or_default();
+        opcode.count += 1;
+        opcode.gas += step.gas_cost;
+        self.gas += step.gas_cost;
+    }
+
+    /// Opens the frame of a nested call
+    fn enter_frame(&mut self, frame: ProfileFrame) {
+        let path = format!("{};{}", self.current().path, frame.label());
+        self.open.push(OpenFrame { frame, path });
+    }
+
+    fn current(&mut self) -> &mut OpenFrame {
+        self.open
+            .last_mut()
+            .expect("the top level frame is never closed")
+    }
+
+    /// Moves the innermost frame into its parent
+    fn close(&mut self) {
+        let frame = self.pop();
+        if let Some(parent) = self.open.last_mut() {
+            parent.frame.total_gas += frame.total_gas;
+            parent.frame.calls.push(frame);
+        }
+    }
+
+    fn pop(&mut self) -> ProfileFrame {
+        let mut frame = self.open.pop().expect("no open frames").frame;
+        frame.total_gas += frame.self_gas;
+        *self.by_selector.entry(frame.selector.clone()).or_default() += frame.self_gas;
+        frame
+    }
+
+    fn finish(mut self) -> GasProfile {
+        while self.open.len() > 1 {
+            self.close();
+        }
+
+        GasProfile {
+            gas: self.gas,
+            call: self.pop(),
+            by_address: self.by_address,
+            by_selector: self.by_selector,
+            by_opcode: self.by_opcode,
+            folded: self
+                .folded
+                .into_iter()
+                .map(|(stack, gas)| format!("{stack} {gas}\n"))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes;
+
+    fn step(instruction: u8, gas_cost: u64) -> Step {
+        Step {
+            pc: 0,
+            instruction,
+            gas: 0,
+            gas_cost,
+            depth: 1,
+            address: Address::default(),
+            refund: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn aggregates_gas_by_frame() {
+        let (caller, callee) = (Address::from([0xaa; 20]), Address::from([0xbb; 20]));
+        let steps = [
+            step(0x60, 3),
+            step(opcodes::CALL, 100),
+            step(0x60, 3),
+            step(opcodes::SSTORE, 20_000),
+            step(opcodes::STOP, 0),
+        ];
+        let root = ProfileFrame::new(CallKind::Call, caller, &[0xa9, 0x05, 0x9c, 0xbb, 0x01]);
+        let mut profiler = GasProfiler::new(&steps, root);
+        profiler.record(0);
+        profiler.record(1);
+        profiler.enter_frame(ProfileFrame::new(CallKind::Call, callee, &[]));
+        profiler.record(2);
+        profiler.record(3);
+        profiler.close();
+        profiler.record(4);
+        let profile = profiler.finish();
+
+        assert_eq!(profile.gas, 20_106);
+        assert_eq!(profile.call.self_gas, 103);
+        assert_eq!(profile.call.total_gas, 20_106);
+        assert_eq!(profile.call.calls.len(), 1);
+        assert_eq!(profile.call.calls[0].selector, "fallback");
+        assert_eq!(profile.call.calls[0].total_gas, 20_003);
+        assert_eq!(profile.by_address[&caller], 103);
+        assert_eq!(profile.by_address[&callee], 20_003);
+        assert_eq!(profile.by_selector["0xa9059cbb"], 103);
+        assert_eq!(profile.by_selector["fallback"], 20_003);
+        assert_eq!(profile.by_opcode["PUSH1"].count, 2);
+        assert_eq!(profile.by_opcode["PUSH1"].gas, 6);
+    }
+
+    #[test]
+    fn folds_stacks_by_frame_and_opcode() {
+        let steps = [step(0x60, 3), step(opcodes::CALL, 100), step(0x60, 3)];
+        let root = ProfileFrame::new(CallKind::Call, Address::from([0xaa; 20]), &[]);
+        let mut profiler = GasProfiler::new(&steps, root);
+        profiler.record(0);
+        profiler.record(1);
+        profiler.enter_frame(ProfileFrame::new(
+            CallKind::Create,
+            Address::from([0xbb; 20]),
+            &[],
+        ));
+        profiler.record(2);
+        // the frames left open by a failed call are closed by `finish`
+        let profile = profiler.finish();
+
+        let caller = format!("0x{}:fallback", "aa".repeat(20));
+        let callee = format!("0x{}:constructor", "bb".repeat(20));
+        assert_eq!(
+            profile.folded,
+            format!("{caller};{callee};PUSH1 3\n{caller};CALL 100\n{caller};PUSH1 3\n")
+        );
+    }
+}
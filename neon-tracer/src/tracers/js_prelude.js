@@ -0,0 +1,206 @@
+// Helpers available to geth JS tracers and the glue which feeds the trace data to them.
+// Every call from Rust passes the data as a JSON string.
+
+function __bytes(hex) {
+    if (hex.startsWith('0x')) {
+        hex = hex.slice(2);
+    }
+    const bytes = new Uint8Array(hex.length / 2);
+    for (let i = 0; i < bytes.length; i++) {
+        bytes[i] = parseInt(hex.substr(i * 2, 2), 16);
+    }
+    return bytes;
+}
+
+function __fit(value, size) {
+    const bytes = typeof value === 'string' ? __bytes(value) : value;
+    const out = new Uint8Array(size);
+    const tail = bytes.slice(Math.max(0, bytes.length - size));
+    out.set(tail, size - tail.length);
+    return out;
+}
+
+function toHex(bytes) {
+    if (typeof bytes === 'string') {
+        return bytes;
+    }
+    let hex = '0x';
+    for (const byte of bytes) {
+        hex += (byte < 16 ? '0' : '') + byte.toString(16);
+    }
+    return hex;
+}
+
+function toWord(value) {
+    return __fit(value, 32);
+}
+
+function toAddress(value) {
+    return __fit(value, 20);
+}
+
+function bigInt(value) {
+    return BigInt(value);
+}
+
+function slice(array, start, end) {
+    return array.slice(start, end);
+}
+
+function isPrecompiled(address) {
+    const bytes = toAddress(address);
+    for (let i = 0; i < 19; i++) {
+        if (bytes[i] !== 0) {
+            return false;
+        }
+    }
+    return bytes[19] >= 1 && bytes[19] <= 9;
+}
+
+function toContract() {
+    throw new Error('toContract is not supported');
+}
+
+function toContract2() {
+    throw new Error('toContract2 is not supported');
+}
+
+function __log(d) {
+    const memoryLength = d.memory.length / 2;
+    const memorySlice = (start, end) =>
+        __pad(d.memory.substring(start * 2, end * 2), Math.max(0, end - start));
+    return {
+        op: {
+            toNumber: () => d.op,
+            toString: () => d.opName,
+            isPush: () => d.op >= 0x60 && d.op <= 0x7f,
+        },
+        stack: {
+            peek: (n) => {
+                if (n < 0 || n >= d.stack.length) {
+                    throw new Error('stack peek out of range: ' + n);
+                }
+                return BigInt(d.stack[d.stack.length - 1 - n]);
+            },
+            length: () => d.stack.length,
+        },
+        memory: {
+            slice: (start, end) => memorySlice(start, end),
+            getUint: (offset) => BigInt(toHex(memorySlice(offset, offset + 32))),
+            length: () => memoryLength,
+        },
+        contract: {
+            getCaller: () => __bytes(d.contract.caller),
+            getAddress: () => __bytes(d.contract.address),
+            getValue: () => BigInt(d.contract.value),
+            getInput: () => __bytes(d.contract.input),
+        },
+        getPC: () => d.pc,
+        getGas: () => d.gas,
+        getCost: () => d.cost,
+        getDepth: () => d.depth,
+        getRefund: () => d.refund,
+        getError: () => d.error,
+    };
+}
+
+// Memory past its current size reads as zeros
+function __pad(hex, size) {
+    const bytes = new Uint8Array(size);
+    bytes.set(__bytes(hex));
+    return bytes;
+}
+
+function __frame(d) {
+    return {
+        getType: () => d.type,
+        getFrom: () => __bytes(d.from),
+        getTo: () => __bytes(d.to),
+        getInput: () => __bytes(d.input),
+        getGas: () => d.gas,
+        getValue: () => (d.value === null ? undefined : BigInt(d.value)),
+    };
+}
+
+function __frameResult(d) {
+    return {
+        getGasUsed: () => d.gasUsed,
+        getOutput: () => __bytes(d.output),
+        getError: () => (d.error === null ? undefined : d.error),
+    };
+}
+
+function __ctx(d) {
+    const ctx = {
+        type: d.type,
+        from: __bytes(d.from),
+        to: __bytes(d.to),
+        input: __bytes(d.input),
+        gas: d.gas,
+        gasUsed: d.gasUsed,
+        gasPrice: BigInt(d.gasPrice),
+        value: BigInt(d.value),
+        output: __bytes(d.output),
+    };
+    if (d.error !== null) {
+        ctx.error = d.error;
+    }
+    return ctx;
+}
+
+const __db = {
+    getBalance: () => { throw new Error('db.getBalance is not supported'); },
+    getNonce: () => { throw new Error('db.getNonce is not supported'); },
+    getCode: () => { throw new Error('db.getCode is not supported'); },
+    getState: () => { throw new Error('db.getState is not supported'); },
+    exists: () => { throw new Error('db.exists is not supported'); },
+};
+
+function __driver(tracer) {
+    if (typeof tracer !== 'object' || tracer === null) {
+        throw new Error('tracer must be an object');
+    }
+    for (const method of ['step', 'fault', 'result']) {
+        if (typeof tracer[method] !== 'function') {
+            throw new Error('tracer object must expose a ' + method + ' function');
+        }
+    }
+    const traceFrames = typeof tracer.enter === 'function' && typeof tracer.exit === 'function';
+
+    return function (kind, json) {
+        const d = JSON.parse(json);
+        switch (kind) {
+            case 'setup':
+                if (typeof tracer.setup === 'function') {
+                    tracer.setup(d);
+                }
+                return;
+            case 'step':
+                tracer.step(__log(d), __db);
+                return;
+            case 'fault':
+                tracer.fault(__log(d), __db);
+                return;
+            case 'enter':
+                if (traceFrames) {
+                    tracer.enter(__frame(d));
+                }
+                return;
+            case 'exit':
+                if (traceFrames) {
+                    tracer.exit(__frameResult(d));
+                }
+                return;
+            case 'result':
+                return JSON.stringify(tracer.result(__ctx(d), __db), (key, value) => {
+                    if (typeof value === 'bigint') {
+                        return value.toString();
+                    }
+                    if (value instanceof Uint8Array) {
+                        return toHex(value);
+                    }
+                    return value;
+                });
+        }
+    };
+}
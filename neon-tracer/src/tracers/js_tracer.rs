@@ -0,0 +1,489 @@
+//! geth-style JavaScript tracers executed by the embedded boa engine.
+//!
+//! The tracer object is evaluated once per transaction in a fresh `Context` without any host
+//! bindings, so the code has no access to the filesystem, network or the tracer process.
+//! The operations are replayed from `TracedCall` and fed to the tracer through the driver
+//! defined in `js_prelude.js`.
+//!
+//! boa can't interrupt a running script nor limit its heap, so every trace runs in a worker
+//! process started from the tracer binary with `WORKER_ARG`. The worker address space is capped
+//! by `RLIMIT_AS`, and the worker is killed once the budget is spent or the request is dropped.
+
+use std::{
+    io::{self, Read, Write},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use boa_engine::{Context, JsError, JsObject, JsString, JsValue, Source};
+use ethnum::U256;
+use evm_loader::evm::tracing::event_listener::trace::TracedCall;
+use evm_loader::types::hexbytes::HexBytes;
+use neon_cli_lib::types::Address;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use super::{
+    exit_status_error, frame_error, frame_output, gas_cost, pushed_word, walk, CallKind,
+    TraceVisitor, TracerError, TxContext, Visit,
+};
+use crate::{opcodes::opcode_name, types::geth::Step};
+
+const PRELUDE: &str = include_str!("js_prelude.js");
+
+/// Iterations of a single JS loop, bounds the work done by one callback
+const LOOP_ITERATION_LIMIT: u64 = 10_000_000;
+const RECURSION_LIMIT: usize = 512;
+
+/// Command line argument starting the tracer binary as a JS tracer worker
+pub const WORKER_ARG: &str = "--js-tracer-worker";
+
+/// Time given to the worker on top of the tracer budget to start and to report the result
+const WORKER_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsTracer {
+    code: String,
+    tracer_config: serde_json::Value,
+    /// Execution budget of the whole trace, including the callbacks
+    timeout: Duration,
+    /// Memory the worker may allocate on top of the trace data, in bytes
+    memory_limit: u64,
+}
+
+/// Input of the worker, read from its stdin
+#[derive(Serialize, Deserialize)]
+struct WorkerRequest {
+    tracer: JsTracer,
+    traced_call: TracedCall,
+    ctx: TxContext,
+}
+
+/// Output of the worker, written to its stdout
+#[derive(Serialize, Deserialize)]
+enum WorkerResponse {
+    Result(serde_json::Value),
+    Timeout,
+    Error(String),
+}
+
+impl JsTracer {
+    pub fn new(
+        code: &str,
+        tracer_config: Option<&serde_json::Value>,
+        timeout: Duration,
+        memory_limit: u64,
+    ) -> Self {
+        Self {
+            code: code.to_string(),
+            tracer_config: tracer_config.cloned().unwrap_or_default(),
+            timeout,
+            memory_limit,
+        }
+    }
+
+    pub async fn trace(
+        &self,
+        traced_call: TracedCall,
+        ctx: &TxContext,
+    ) -> Result<serde_json::Value, TracerError> {
+        let request = WorkerRequest {
+            tracer: self.clone(),
+            traced_call,
+            ctx: ctx.clone(),
+        };
+        let input = serde_json::to_vec(&request).map_err(|e| TracerError::Js(e.to_string()))?;
+
+        let mut worker = Command::new(std::env::current_exe().map_err(worker_error)?)
+            .arg(WORKER_ARG)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // the worker is killed once the future is dropped: on timeout or with the request
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(worker_error)?;
+        let mut stdin = worker.stdin.take().expect("worker stdin is piped");
+        let run = async move {
+            stdin.write_all(&input).await?;
+            drop(stdin);
+            worker.wait_with_output().await
+        };
+
+        let output = tokio::time::timeout(self.timeout + WORKER_GRACE, run)
+            .await
+            .map_err(|_| TracerError::Timeout)?
+            .map_err(worker_error)?;
+        if !output.status.success() {
+            return Err(TracerError::Js(format!(
+                "tracer worker failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        match serde_json::from_slice(&output.stdout).map_err(|e| TracerError::Js(e.to_string()))? {
+            WorkerResponse::Result(result) => Ok(result),
+            WorkerResponse::Timeout => Err(TracerError::Timeout),
+            WorkerResponse::Error(e) => Err(TracerError::Js(e)),
+        }
+    }
+
+    fn run(
+        &self,
+        traced_call: &TracedCall,
+        ctx: &TxContext,
+    ) -> Result<serde_json::Value, TracerError> {
+        let mut runtime = JsRuntime::new(&self.code, Instant::now() + self.timeout)?;
+        runtime.dispatch("setup", &self.tracer_config)?;
+
+        let (kind, to) = match ctx.to {
+            Some(to) => (CallKind::Call, to),
+            None => (
+                CallKind::Create,
+                ctx.nonce
+                    .map(|nonce| Address::from_create(&ctx.from, nonce))
+                    .unwrap_or_default(),
+            ),
+        };
+        let top = Frame {
+            caller: ctx.from,
+            address: to,
+            value: ctx.value,
+            input: ctx.input.clone(),
+        };
+
+        let mut walker = Walker {
+            runtime,
+            steps: Step::collect(traced_call, ctx),
+            frames: vec![(top, true)],
+        };
+        walk(traced_call, &mut walker)?;
+
+        let result_ctx = ResultContext {
+            kind,
+            from: ctx.from,
+            to,
+            input: ctx.input.clone().into(),
+            gas: ctx.gas.unwrap_or(traced_call.used_gas),
+            gas_used: traced_call.used_gas,
+            gas_price: ctx.gas_price.unwrap_or_default(),
+            value: ctx.value,
+            output: traced_call.result.clone().into(),
+            error: exit_status_error(&traced_call.exit_status),
+        };
+        let result = walker.runtime.dispatch("result", &result_ctx)?;
+
+        match result.as_string() {
+            Some(json) => serde_json::from_str(&json.to_std_string_escaped())
+                .map_err(|e| TracerError::Js(e.to_string())),
+            // JSON.stringify returns undefined for undefined and functions
+            None => Ok(serde_json::Value::Null),
+        }
+    }
+}
+
+/// Runs the JS tracer of the `WorkerRequest` read from stdin,
+/// writes `WorkerResponse` to stdout
+pub fn run_worker() {
+    let response = match serve_worker_request() {
+        Ok(result) => WorkerResponse::Result(result),
+        Err(TracerError::Timeout) => WorkerResponse::Timeout,
+        Err(e) => WorkerResponse::Error(e.to_string()),
+    };
+
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &response).expect("failed to write the tracer result");
+    stdout.flush().expect("failed to write the tracer result");
+}
+
+fn serve_worker_request() -> Result<serde_json::Value, TracerError> {
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input).map_err(worker_error)?;
+    let request: WorkerRequest =
+        serde_json::from_slice(&input).map_err(|e| TracerError::Js(e.to_string()))?;
+    drop(input);
+
+    limit_memory(request.tracer.memory_limit).map_err(worker_error)?;
+    request.tracer.run(&request.traced_call, &request.ctx)
+}
+
+/// Caps the address space of the process at its current size plus `budget`,
+/// allocations past the limit abort the worker
+fn limit_memory(budget: u64) -> io::Result<()> {
+    let statm = std::fs::read_to_string("/proc/self/statm")?;
+    let pages: u64 = statm
+        .split_whitespace()
+        .next()
+        .and_then(|pages| pages.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed /proc/self/statm"))?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) })
+        .map_err(|_| io::Error::last_os_error())?;
+
+    let limit = pages.saturating_mul(page_size).saturating_add(budget);
+    let rlimit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    // SAFETY: `rlimit` is a valid pointer for the duration of the call
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &rlimit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn worker_error(e: io::Error) -> TracerError {
+    TracerError::Js(format!("tracer worker error: {e}"))
+}
+
+struct JsRuntime {
+    context: Context<'static>,
+    driver: JsObject,
+    deadline: Instant,
+}
+
+impl JsRuntime {
+    fn new(code: &str, deadline: Instant) -> Result<Self, TracerError> {
+        let mut context = Context::default();
+        context
+            .runtime_limits_mut()
+            .set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+        context
+            .runtime_limits_mut()
+            .set_recursion_limit(RECURSION_LIMIT);
+
+        context
+            .eval(Source::from_bytes(PRELUDE))
+            .map_err(js_error)?;
+        // the tracer is an object literal, the same way geth evaluates it
+        let driver = context
+            .eval(Source::from_bytes(&format!("__driver(({code}\n))")))
+            .map_err(js_error)?;
+        let driver = driver
+            .as_callable()
+            .cloned()
+            .ok_or_else(|| TracerError::Js("tracer driver is not callable".to_string()))?;
+
+        Ok(Self {
+            context,
+            driver,
+            deadline,
+        })
+    }
+
+    fn dispatch(&mut self, kind: &str, payload: &impl Serialize) -> Result<JsValue, TracerError> {
+        if Instant::now() > self.deadline {
+            return Err(TracerError::Timeout);
+        }
+
+        let payload = serde_json::to_string(payload).map_err(|e| TracerError::Js(e.to_string()))?;
+        let args = [
+            JsValue::from(JsString::from(kind)),
+            JsValue::from(JsString::from(payload)),
+        ];
+        self.driver
+            .call(&JsValue::undefined(), &args, &mut self.context)
+            .map_err(js_error)
+    }
+}
+
+fn js_error(e: JsError) -> TracerError {
+    TracerError::Js(e.to_string())
+}
+
+/// Execution context of the contract code, exposed as `log.contract`
+#[derive(Serialize, Debug, Clone)]
+struct Frame {
+    caller: Address,
+    address: Address,
+    value: U256,
+    #[serde(serialize_with = "serialize_hex")]
+    input: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StepLog<'a> {
+    op: u8,
+    op_name: &'static str,
+    pc: usize,
+    gas: u64,
+    cost: u64,
+    depth: usize,
+    refund: u64,
+    error: Option<String>,
+    stack: Vec<U256>,
+    /// Hex without prefix, sliced by byte offsets in JS
+    memory: String,
+    contract: &'a Frame,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameStart {
+    #[serde(rename = "type")]
+    kind: CallKind,
+    from: Address,
+    to: Address,
+    input: HexBytes,
+    gas: u64,
+    value: Option<U256>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameEnd {
+    gas_used: u64,
+    output: HexBytes,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResultContext {
+    #[serde(rename = "type")]
+    kind: CallKind,
+    from: Address,
+    to: Address,
+    input: HexBytes,
+    gas: u64,
+    gas_used: u64,
+    gas_price: U256,
+    value: U256,
+    output: HexBytes,
+    error: Option<String>,
+}
+
+fn serialize_hex<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
+fn to_u64(value: U256) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+/// Reports the operations and the nested calls to the tracer
+struct Walker {
+    runtime: JsRuntime,
+    /// Gas, refund and error of the steps, the same as reported by structLogs
+    steps: Vec<Step>,
+    /// Open frames, the top level call first, with the flag telling whether the tracer has
+    /// entered them: the callee is unknown without the stack snapshot, only its steps are reported
+    frames: Vec<(Frame, bool)>,
+}
+
+impl<'t> TraceVisitor<'t> for Walker {
+    type Error = TracerError;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), TracerError> {
+        let operation = visit.operation;
+        let (frame, _) = self
+            .frames
+            .last()
+            .expect("the top level frame is never closed");
+        let step = self.steps.get(visit.step);
+        let log = StepLog {
+            op: operation.instruction,
+            op_name: opcode_name(operation.instruction),
+            pc: operation.pc,
+            gas: step.map_or(0, |step| step.gas),
+            cost: step.map_or_else(|| to_u64(operation.gas_cost), |step| step.gas_cost),
+            depth: visit.depth,
+            refund: step.map_or(0, |step| step.refund),
+            error: step
+                .and_then(|step| step.error.clone())
+                .or_else(|| frame_error(operation)),
+            stack: visit.data.map_or(vec![], |d| {
+                d.stack
+                    .iter()
+                    .map(|word| U256::from_be_bytes(*word))
+                    .collect()
+            }),
+            memory: visit.data.map_or(String::new(), |d| hex::encode(&d.memory)),
+            contract: frame,
+        };
+        self.runtime.dispatch("step", &log)?;
+
+        // REVERT, INVALID and out of gas end the frame
+        if log.error.is_some() {
+            self.runtime.dispatch("fault", &log)?;
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self, visit: &Visit<'t>) -> Result<bool, TracerError> {
+        let (parent, _) = self
+            .frames
+            .last()
+            .expect("the top level frame is never closed");
+        let Some(site) = visit.call_site() else {
+            let frame = parent.clone();
+            self.frames.push((frame, false));
+            return Ok(true);
+        };
+
+        let gas_used = visit.sub.map_or(U256::ZERO, gas_cost);
+        self.runtime.dispatch(
+            "enter",
+            &FrameStart {
+                kind: site.kind,
+                from: parent.address,
+                to: site.to.unwrap_or_default(),
+                input: site.input.clone().into(),
+                gas: to_u64(site.gas.unwrap_or(gas_used)),
+                value: site.value,
+            },
+        )?;
+
+        // DELEGATECALL and CALLCODE execute the callee code in the context of the caller
+        let frame = match site.kind {
+            CallKind::DelegateCall => Frame {
+                caller: parent.caller,
+                address: parent.address,
+                value: parent.value,
+                input: site.input,
+            },
+            CallKind::CallCode => Frame {
+                caller: parent.address,
+                address: parent.address,
+                value: site.value.unwrap_or_default(),
+                input: site.input,
+            },
+            _ => Frame {
+                caller: parent.address,
+                address: site.to.unwrap_or_default(),
+                value: site.value.unwrap_or_default(),
+                input: site.input,
+            },
+        };
+        self.frames.push((frame, true));
+
+        Ok(true)
+    }
+
+    fn exit(&mut self, visit: &Visit<'t>, last: Option<&Visit<'t>>) -> Result<(), TracerError> {
+        let (_, entered) = self.frames.pop().expect("the nested call frame is open");
+        if !entered {
+            return Ok(());
+        }
+
+        let mut end = FrameEnd {
+            gas_used: to_u64(visit.sub.map_or(U256::ZERO, gas_cost)),
+            output: HexBytes::from(vec![]),
+            error: None,
+        };
+        if let Some(last) = last {
+            if let Some(output) = last.data.and_then(|d| frame_output(last.operation, d)) {
+                end.output = output.into();
+            }
+            end.error = frame_error(last.operation);
+        }
+        if end.error.is_none() && pushed_word(visit.operation) == Some(U256::ZERO) {
+            end.error = Some("execution failed".to_string());
+        }
+
+        self.runtime.dispatch("exit", &end)
+    }
+}
@@ -0,0 +1,765 @@
+//! Native implementations of the geth built-in tracers.
+//!
+//! Neon API returns the same `TracedCall` for every tracer: the `VMTrace` hierarchy of executed
+//! operations and a per-step snapshot in `full_trace_data`. The tracers replay that data and
+//! reshape it into the output format of the corresponding geth tracer.
+
+mod call_tracer;
+mod four_byte_tracer;
+mod gas_profiler;
+mod js_tracer;
+mod parity;
+mod prestate_tracer;
+mod struct_logger;
+mod walk;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use ethnum::U256;
+use evm_loader::evm::tracing::event_listener::trace::{
+    FullTraceData, TraceConfig, TracedCall, VMOperation, VMTrace,
+};
+use neon_cli_lib::types::Address;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use js_tracer::JsTracer;
+
+use crate::{
+    api_client::config::Config,
+    data_source::{DataSource, ERR, TIMEOUT},
+    opcodes,
+    service::Error,
+    types::{
+        geth::{Eip3155Trace, ExecutionResult, Trace, TransactionArgs},
+        raw_transaction::RawTransaction,
+    },
+};
+
+pub use call_tracer::{CallFrame, CallTracerConfig};
+pub use four_byte_tracer::four_byte_trace;
+pub use gas_profiler::GasProfile;
+pub use js_tracer::{run_worker as run_js_worker, WORKER_ARG as JS_WORKER_ARG};
+pub use parity::{
+    localized_traces, parity_call_config, parity_trace_config, Action, CallOutput, FlatTraceResult,
+    LocalizedTrace, TraceResults, TraceResultsWithTransactionHash, TxLocation,
+};
+pub use prestate_tracer::{
+    storage_writes, PrestateResult, PrestateTracerConfig, StateChanges, StateOverlay,
+};
+pub use struct_logger::{LogFormat, StructLogger};
+pub use walk::{walk, TraceVisitor, Visit};
+
+#[derive(Error, Debug)]
+pub enum TracerError {
+    #[error("Unknown tracer: {0}")]
+    UnknownTracer(String),
+
+    #[error("Invalid tracerConfig: {0}")]
+    InvalidConfig(#[from] serde_json::Error),
+
+    #[error("Invalid timeout: {0}")]
+    InvalidTimeout(String),
+
+    #[error("execution timeout")]
+    Timeout,
+
+    #[error("JS tracer error: {0}")]
+    Js(String),
+
+    #[error("Invalid state overrides: {0}")]
+    StateOverrides(serde_json::Error),
+
+    #[error("Invalid overrides: {0}")]
+    InvalidOverrides(String),
+
+    #[error("Invalid structLog filter: {0}")]
+    InvalidStructLogFilter(String),
+
+    #[error("DataSourceError: {0:?}")]
+    DataSource(Error),
+}
+
+impl TracerError {
+    pub fn into_rpc_error(self, id: u64) -> Error {
+        match self {
+            Self::DataSource(e) => e,
+            Self::Timeout => TIMEOUT(&self.to_string(), id),
+            other => ERR(&other.to_string(), id),
+        }
+    }
+
+    /// Error message reported in place of the trace of a block transaction
+    pub fn message(&self) -> String {
+        match self {
+            Self::DataSource(e) => e.message().to_string(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Tracer selected by the `tracer` field of the trace config
+#[derive(Debug, Clone)]
+pub enum Tracer {
+    /// Default opcode logger producing `structLogs`
+    StructLogger(StructLogger),
+    Call(CallTracerConfig),
+    Prestate(PrestateTracerConfig),
+    FourByte,
+    /// Gas of the steps by call frame, contract, selector and opcode
+    GasProfiler,
+    /// Custom tracer object written in JavaScript
+    Js(JsTracer),
+    /// Runs several tracers over the same `TracedCall`, results are keyed by the tracer name
+    Mux(Vec<(String, Tracer)>),
+    /// Discards the trace, measures the tracing overhead
+    Noop,
+}
+
+/// Result of `noopTracer`, serialized as an empty object
+#[derive(Serialize, Debug, Clone)]
+pub struct NoopResult {}
+
+/// Default execution budget of the JS tracers, the same as in geth
+const DEFAULT_JS_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Tracer {
+    pub fn new(
+        config: &TraceConfig,
+        tracer_config: Option<&serde_json::Value>,
+        limits: &Config,
+    ) -> Result<Self, TracerError> {
+        match config.tracer.as_deref() {
+            None | Some("") => Ok(Self::StructLogger(StructLogger::new(parse_tracer_config(
+                tracer_config,
+            )?)?)),
+            Some("callTracer") => Ok(Self::Call(parse_tracer_config(tracer_config)?)),
+            Some("prestateTracer") => Ok(Self::Prestate(parse_tracer_config(tracer_config)?)),
+            Some("4byteTracer") => Ok(Self::FourByte),
+            Some("gasProfiler") => Ok(Self::GasProfiler),
+            Some("noopTracer") => Ok(Self::Noop),
+            Some("muxTracer") => {
+                let configs: BTreeMap<String, serde_json::Value> =
+                    parse_tracer_config(tracer_config)?;
+                let tracers = configs
+                    .into_iter()
+                    .map(|(name, tracer_config)| {
+                        if name == "muxTracer" {
+                            return Err(TracerError::UnknownTracer(
+                                "muxTracer can not be nested".to_string(),
+                            ));
+                        }
+                        let config = TraceConfig {
+                            tracer: Some(name.clone()),
+                            ..config.clone()
+                        };
+                        let tracer = Self::new(&config, Some(&tracer_config), limits)?;
+                        Ok((name, tracer))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Self::Mux(tracers))
+            }
+            Some(code) if code.trim_start().starts_with('{') => {
+                // bounded the same way as the request timeout
+                let timeout = client_timeout(
+                    config.timeout.as_deref(),
+                    DEFAULT_JS_TIMEOUT,
+                    limits.timeouts.max,
+                )?;
+                Ok(Self::Js(JsTracer::new(
+                    code,
+                    tracer_config,
+                    timeout,
+                    limits.js_tracer_memory,
+                )))
+            }
+            Some(other) => Err(TracerError::UnknownTracer(other.to_string())),
+        }
+    }
+
+    /// Native tracers decode call arguments from the stack and memory of every step,
+    /// so both are requested from Neon API regardless of the client options.
+    pub fn upstream_config(&self, config: &TraceConfig) -> TraceConfig {
+        let mut config = config.clone();
+        match self {
+            // EIP-3155 reports the stack and the memory size of every step
+            Self::StructLogger(logger) if logger.format == LogFormat::Eip3155 => {
+                config.enable_memory = true;
+                config.disable_stack = false;
+            }
+            Self::StructLogger(_) => {}
+            Self::Noop => {
+                config.enable_memory = false;
+                config.disable_stack = true;
+                config.disable_storage = true;
+                config.enable_return_data = false;
+                config.tracer = None;
+            }
+            _ => {
+                if self.needs_context() {
+                    config.enable_memory = true;
+                    config.disable_stack = false;
+                }
+                config.tracer = None;
+            }
+        }
+        config
+    }
+
+    /// Whether the tracer needs the transaction fields missing in `TracedCall`
+    pub fn needs_context(&self) -> bool {
+        match self {
+            Self::StructLogger(_) | Self::Noop => false,
+            Self::Mux(tracers) => tracers.iter().any(|(_, tracer)| tracer.needs_context()),
+            _ => true,
+        }
+    }
+
+    /// Whether the tracer reports the state the transaction is executed on,
+    /// which includes the changes made by the preceding transactions of the block
+    pub fn needs_block_state(&self) -> bool {
+        match self {
+            Self::Prestate(_) => true,
+            Self::Mux(tracers) => tracers.iter().any(|(_, tracer)| tracer.needs_block_state()),
+            _ => false,
+        }
+    }
+
+    /// Whether the transaction is worth fetching: the struct logger does without it,
+    /// but takes the gas limit from it to report the remaining gas
+    pub fn needs_transaction(&self) -> bool {
+        match self {
+            Self::StructLogger(_) => true,
+            Self::Mux(tracers) => tracers.iter().any(|(_, tracer)| tracer.needs_transaction()),
+            other => other.needs_context(),
+        }
+    }
+
+    /// Reshapes the traced call, `overlay` holds the changes made by the transactions
+    /// executed before it on top of the `ctx.slot` state
+    pub async fn trace(
+        &self,
+        traced_call: TracedCall,
+        config: &TraceConfig,
+        ctx: &TxContext,
+        overlay: &StateOverlay,
+        source: &DataSource,
+        id: u64,
+    ) -> Result<Trace, TracerError> {
+        let Self::Mux(tracers) = self else {
+            return self
+                .trace_single(traced_call, config, ctx, overlay, source, id)
+                .await;
+        };
+
+        let mut traces = BTreeMap::new();
+        for (name, tracer) in tracers {
+            let trace = tracer
+                .trace_single(traced_call.clone(), config, ctx, overlay, source, id)
+                .await?;
+            traces.insert(name.clone(), trace);
+        }
+        Ok(Trace::Mux(traces))
+    }
+
+    /// Runs a tracer other than `muxTracer`, kept apart from `trace` to avoid recursive async
+    async fn trace_single(
+        &self,
+        traced_call: TracedCall,
+        config: &TraceConfig,
+        ctx: &TxContext,
+        overlay: &StateOverlay,
+        source: &DataSource,
+        id: u64,
+    ) -> Result<Trace, TracerError> {
+        match self {
+            Self::StructLogger(logger) => {
+                let result = ExecutionResult::new(traced_call, config, ctx, logger);
+                match logger.format {
+                    LogFormat::Eip3155 => Ok(Trace::Eip3155(Eip3155Trace(result))),
+                    _ => Ok(Trace::Logs(result)),
+                }
+            }
+            Self::Call(call_config) => {
+                Ok(Trace::Call(CallFrame::new(&traced_call, ctx, call_config)))
+            }
+            Self::Prestate(prestate_config) => Ok(Trace::Prestate(
+                PrestateResult::new(&traced_call, ctx, prestate_config, overlay, source, id)
+                    .await?,
+            )),
+            Self::FourByte => Ok(Trace::FourByte(four_byte_trace(&traced_call, ctx))),
+            Self::GasProfiler => Ok(Trace::GasProfile(GasProfile::new(&traced_call, ctx))),
+            Self::Js(js_tracer) => Ok(Trace::Js(js_tracer.trace(traced_call, ctx).await?)),
+            Self::Noop => Ok(Trace::Noop(NoopResult {})),
+            Self::Mux(_) => Err(TracerError::UnknownTracer(
+                "muxTracer can not be nested".to_string(),
+            )),
+        }
+    }
+}
+
+fn parse_tracer_config<T: DeserializeOwned + Default>(
+    tracer_config: Option<&serde_json::Value>,
+) -> Result<T, TracerError> {
+    match tracer_config {
+        None | Some(serde_json::Value::Null) => Ok(T::default()),
+        Some(value) => Ok(serde_json::from_value(value.clone())?),
+    }
+}
+
+/// Parses a Go duration string like "5s", "1m30s" or "300ms" used by the geth `timeout` option
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut rest = duration.trim();
+    if rest == "0" {
+        return Some(Duration::ZERO);
+    }
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total = total.checked_add(Duration::try_from_secs_f64(value * scale).ok()?)?;
+    }
+
+    Some(total)
+}
+
+/// Timeout set by the geth `timeout` option capped by `max`, `default` if the option is not set
+pub fn client_timeout(
+    timeout: Option<&str>,
+    default: Duration,
+    max: Duration,
+) -> Result<Duration, TracerError> {
+    match timeout {
+        None | Some("") => Ok(default),
+        Some(timeout) => parse_duration(timeout)
+            .map(|timeout| timeout.min(max))
+            .ok_or_else(|| TracerError::InvalidTimeout(timeout.to_string())),
+    }
+}
+
+/// Transaction fields which are not part of `TracedCall`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TxContext {
+    pub from: Address,
+    pub to: Option<Address>,
+    pub value: U256,
+    pub input: Vec<u8>,
+    pub gas: Option<u64>,
+    pub gas_price: Option<U256>,
+    pub nonce: Option<u64>,
+    /// Position of the mined transaction in its block
+    pub position: Option<usize>,
+    /// Slot of the state the transaction is executed on,
+    /// the parent slot for the mined transactions
+    pub slot: u64,
+}
+
+impl TxContext {
+    /// Gas charged before the execution starts, the access list is not taken into account
+    pub fn intrinsic_gas(&self) -> u64 {
+        let base: u64 = if self.to.is_some() { 21_000 } else { 53_000 };
+        let zeros = self.input.iter().filter(|byte| **byte == 0).count() as u64;
+        let non_zeros = self.input.len() as u64 - zeros;
+        base + zeros * 4 + non_zeros * 16
+    }
+}
+
+impl From<&web3::types::Transaction> for TxContext {
+    fn from(tx: &web3::types::Transaction) -> Self {
+        let to_u256 = |value: web3::types::U256| {
+            let mut bytes = [0u8; 32];
+            value.to_big_endian(&mut bytes);
+            U256::from_be_bytes(bytes)
+        };
+
+        Self {
+            from: tx.from.map(|a| Address::from(a.0)).unwrap_or_default(),
+            to: tx.to.map(|a| Address::from(a.0)),
+            value: to_u256(tx.value),
+            input: tx.input.0.clone(),
+            gas: Some(tx.gas.low_u64()),
+            gas_price: tx.gas_price.map(to_u256),
+            nonce: Some(tx.nonce.low_u64()),
+            position: tx.transaction_index.map(|index| index.as_usize()),
+            slot: tx
+                .block_number
+                .map_or(0, |number| number.as_u64().saturating_sub(1)),
+        }
+    }
+}
+
+impl From<&TransactionArgs> for TxContext {
+    fn from(args: &TransactionArgs) -> Self {
+        Self {
+            from: args.from.unwrap_or_default(),
+            to: args.to,
+            value: args.value.unwrap_or_default(),
+            input: args.input.as_ref().map(|a| a.0.clone()).unwrap_or_default(),
+            gas: args.gas.map(|g| g.as_u64()),
+            gas_price: args.gas_price,
+            nonce: args.nonce.map(|n| n.as_u64()),
+            position: None,
+            slot: 0,
+        }
+    }
+}
+
+impl From<&RawTransaction> for TxContext {
+    fn from(tx: &RawTransaction) -> Self {
+        Self {
+            from: tx.from,
+            to: tx.to,
+            value: tx.value,
+            input: tx.input.clone(),
+            gas: Some(tx.gas),
+            gas_price: Some(tx.gas_price),
+            nonce: Some(tx.nonce),
+            position: None,
+            slot: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CallKind {
+    Call,
+    CallCode,
+    DelegateCall,
+    StaticCall,
+    Create,
+    Create2,
+}
+
+impl CallKind {
+    pub fn from_opcode(instruction: u8) -> Option<Self> {
+        match instruction {
+            opcodes::CALL => Some(Self::Call),
+            opcodes::CALLCODE => Some(Self::CallCode),
+            opcodes::DELEGATECALL => Some(Self::DelegateCall),
+            opcodes::STATICCALL => Some(Self::StaticCall),
+            opcodes::CREATE => Some(Self::Create),
+            opcodes::CREATE2 => Some(Self::Create2),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments of a CALL* or CREATE* instruction decoded from the step snapshot
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    pub kind: CallKind,
+    /// Callee, or the created contract address if the creation succeeded
+    pub to: Option<Address>,
+    pub value: Option<U256>,
+    /// Gas limit passed to the callee, not known for CREATE*
+    pub gas: Option<U256>,
+    /// Call data or init code
+    pub input: Vec<u8>,
+}
+
+impl CallSite {
+    pub fn decode(operation: &VMOperation, data: &FullTraceData) -> Option<Self> {
+        let kind = CallKind::from_opcode(operation.instruction)?;
+        let arg = |n| stack_item(data, n);
+
+        let site = match kind {
+            CallKind::Call | CallKind::CallCode => Self {
+                kind,
+                to: Some(word_to_address(arg(1)?)),
+                value: Some(arg(2)?),
+                gas: Some(arg(0)?),
+                input: memory_slice(data, arg(3)?, arg(4)?),
+            },
+            CallKind::DelegateCall | CallKind::StaticCall => Self {
+                kind,
+                to: Some(word_to_address(arg(1)?)),
+                value: None,
+                gas: Some(arg(0)?),
+                input: memory_slice(data, arg(2)?, arg(3)?),
+            },
+            CallKind::Create | CallKind::Create2 => Self {
+                kind,
+                to: pushed_word(operation)
+                    .filter(|word| *word != U256::ZERO)
+                    .map(word_to_address),
+                value: Some(arg(0)?),
+                gas: None,
+                input: memory_slice(data, arg(1)?, arg(2)?),
+            },
+        };
+
+        Some(site)
+    }
+}
+
+/// 32 bytes word serialized as a zero-padded hex string, the way geth prints hashes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Word(pub U256);
+
+impl Serialize for Word {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("0x{}", hex::encode(self.0.to_be_bytes())))
+    }
+}
+
+impl<'de> Deserialize<'de> for Word {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        U256::deserialize(deserializer).map(Word)
+    }
+}
+
+/// Returns the `n`-th item from the top of the stack
+pub fn stack_item(data: &FullTraceData, n: usize) -> Option<U256> {
+    let index = data.stack.len().checked_sub(n + 1)?;
+    Some(U256::from_be_bytes(data.stack[index]))
+}
+
+/// Returns `size` bytes of memory starting at `offset`, zero-padded past the end of memory
+pub fn memory_slice(data: &FullTraceData, offset: U256, size: U256) -> Vec<u8> {
+    let (Ok(offset), Ok(size)) = (usize::try_from(offset), usize::try_from(size)) else {
+        return vec![];
+    };
+    if size == 0 {
+        return vec![];
+    }
+
+    let mut slice = vec![0u8; size];
+    if offset < data.memory.len() {
+        let end = data.memory.len().min(offset.saturating_add(size));
+        slice[..end - offset].copy_from_slice(&data.memory[offset..end]);
+    }
+    slice
+}
+
+/// First value pushed onto the stack by the operation: the success flag of CALL*
+/// or the created address of CREATE*
+pub fn pushed_word(operation: &VMOperation) -> Option<U256> {
+    operation
+        .executed
+        .as_ref()
+        .and_then(|executed| executed.stack_push.first())
+        .map(|word| U256::from_be_bytes(*word))
+}
+
+pub fn word_to_address(word: U256) -> Address {
+    let bytes = word.to_be_bytes();
+    Address::from(*arrayref::array_ref![bytes, 12, 20])
+}
+
+/// Total number of operations in the trace including all nested calls
+pub fn step_count(vm_trace: &VMTrace) -> usize {
+    vm_trace.operations.len() + vm_trace.subs.iter().map(step_count).sum::<usize>()
+}
+
+/// Total gas cost of the operations in the trace including all nested calls
+pub fn gas_cost(vm_trace: &VMTrace) -> U256 {
+    vm_trace
+        .operations
+        .iter()
+        .map(|operation| operation.gas_cost)
+        .chain(vm_trace.subs.iter().map(gas_cost))
+        .fold(U256::ZERO, |acc, cost| acc.saturating_add(cost))
+}
+
+/// Data returned by the terminating RETURN or REVERT of a call
+pub fn frame_output(last: &VMOperation, data: &FullTraceData) -> Option<Vec<u8>> {
+    match last.instruction {
+        opcodes::RETURN | opcodes::REVERT => Some(memory_slice(
+            data,
+            stack_item(data, 0)?,
+            stack_item(data, 1)?,
+        )),
+        _ => None,
+    }
+}
+
+pub fn frame_error(last: &VMOperation) -> Option<String> {
+    match last.instruction {
+        opcodes::REVERT => Some("execution reverted".to_string()),
+        opcodes::INVALID => Some("invalid opcode: INVALID".to_string()),
+        _ => None,
+    }
+}
+
+/// Whether the top level call ended with REVERT
+pub fn is_reverted(exit_status: &str) -> bool {
+    exit_status.eq_ignore_ascii_case("revert")
+}
+
+/// Error of the top level call derived from `TracedCall::exit_status`
+pub fn exit_status_error(exit_status: &str) -> Option<String> {
+    match exit_status.to_ascii_lowercase().as_str() {
+        "succeed" => None,
+        "revert" => Some("execution reverted".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Decodes the reason of `Error(string)` and `Panic(uint256)` revert data,
+/// the data of custom errors is not decoded
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+    if let Some(payload) = data.strip_prefix(&PANIC_SELECTOR[..]) {
+        return panic_reason(payload);
+    }
+
+    let payload = data.strip_prefix(&ERROR_SELECTOR[..])?;
+    let offset = abi_word_to_usize(payload, 0)?;
+    let len = abi_word_to_usize(payload, offset)?;
+    let start = offset.checked_add(32)?;
+    let reason = payload.get(start..start.checked_add(len)?)?;
+
+    String::from_utf8(reason.to_vec()).ok()
+}
+
+/// Describes the Solidity panic code the same way as geth
+fn panic_reason(payload: &[u8]) -> Option<String> {
+    let code = payload.get(..32)?;
+    let code = U256::from_be_bytes(*arrayref::array_ref![code, 0, 32]);
+    let reason = match u8::try_from(code) {
+        Ok(0x00) => "generic panic",
+        Ok(0x01) => "assert(false)",
+        Ok(0x11) => "arithmetic underflow or overflow",
+        Ok(0x12) => "division or modulo by zero",
+        Ok(0x21) => "enum overflow",
+        Ok(0x22) => "invalid encoded storage byte array accessed",
+        Ok(0x31) => "out-of-bounds array access; popping on an empty array",
+        Ok(0x32) => "out-of-bounds access of an array or bytesN",
+        Ok(0x41) => "out of memory",
+        Ok(0x51) => "uninitialized function",
+        _ => return Some(format!("unknown panic code: {code:#x}")),
+    };
+    Some(reason.to_string())
+}
+
+fn abi_word_to_usize(data: &[u8], position: usize) -> Option<usize> {
+    let word = data.get(position..position.checked_add(32)?)?;
+    let word = U256::from_be_bytes(*arrayref::array_ref![word, 0, 32]);
+    usize::try_from(word).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_go_durations() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("5s"), Some(ms(5_000)));
+        assert_eq!(parse_duration("300ms"), Some(ms(300)));
+        assert_eq!(parse_duration("1m30s"), Some(ms(90_000)));
+        assert_eq!(parse_duration("1.5h"), Some(ms(5_400_000)));
+        assert_eq!(parse_duration("250us"), Some(Duration::from_micros(250)));
+        assert_eq!(parse_duration("250µs"), Some(Duration::from_micros(250)));
+        assert_eq!(parse_duration("10ns"), Some(Duration::from_nanos(10)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for duration in ["", "5", "s", "5x", "-5s", "5s5", "1..5s"] {
+            assert_eq!(parse_duration(duration), None, "{duration:?}");
+        }
+    }
+
+    #[test]
+    fn client_timeout_is_capped() {
+        let default = Duration::from_secs(10);
+        let max = Duration::from_secs(60);
+        let timeout = |option| client_timeout(option, default, max).ok();
+
+        assert_eq!(timeout(None), Some(default));
+        assert_eq!(timeout(Some("")), Some(default));
+        assert_eq!(timeout(Some("30s")), Some(Duration::from_secs(30)));
+        assert_eq!(timeout(Some("1h")), Some(max));
+        assert!(matches!(
+            client_timeout(Some("forever"), default, max),
+            Err(TracerError::InvalidTimeout(_))
+        ));
+    }
+
+    fn abi_word(value: u64) -> [u8; 32] {
+        U256::from(value).to_be_bytes()
+    }
+
+    fn error_data(reason: &[u8]) -> Vec<u8> {
+        let mut padded = reason.to_vec();
+        padded.resize((reason.len() + 31) / 32 * 32, 0);
+        [
+            &[0x08, 0xc3, 0x79, 0xa0][..],
+            &abi_word(0x20),
+            &abi_word(reason.len() as u64),
+            &padded,
+        ]
+        .concat()
+    }
+
+    fn panic_data(code: u64) -> Vec<u8> {
+        [&[0x4e, 0x48, 0x7b, 0x71][..], &abi_word(code)].concat()
+    }
+
+    #[test]
+    fn decodes_error_reason() {
+        let reason = decode_revert_reason(&error_data(b"insufficient balance"));
+        assert_eq!(reason.as_deref(), Some("insufficient balance"));
+        assert_eq!(decode_revert_reason(&error_data(b"")).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn decodes_panic_code() {
+        let reason = |code| decode_revert_reason(&panic_data(code));
+        assert_eq!(reason(0x01).as_deref(), Some("assert(false)"));
+        assert_eq!(
+            reason(0x11).as_deref(),
+            Some("arithmetic underflow or overflow")
+        );
+        assert_eq!(reason(0x99).as_deref(), Some("unknown panic code: 0x99"));
+    }
+
+    #[test]
+    fn ignores_malformed_revert_data() {
+        let mut truncated = error_data(b"insufficient balance");
+        truncated.truncate(truncated.len() - 32);
+        let invalid_utf8 = error_data(&[0xff, 0xfe]);
+        let mut custom_error = error_data(b"insufficient balance");
+        custom_error[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        assert_eq!(decode_revert_reason(&[]), None);
+        assert_eq!(decode_revert_reason(&[0x08, 0xc3, 0x79]), None);
+        assert_eq!(decode_revert_reason(&panic_data(1)[..20]), None);
+        assert_eq!(decode_revert_reason(&truncated), None);
+        assert_eq!(decode_revert_reason(&invalid_utf8), None);
+        assert_eq!(decode_revert_reason(&custom_error), None);
+    }
+}
@@ -0,0 +1,530 @@
+//! Trace formats of the OpenEthereum `trace_*` methods.
+//!
+//! `trace` is the call tree of `callTracer` flattened in pre-order, `vmTrace` mirrors `VMTrace`
+//! and `stateDiff` is built from the same pre and post state as `prestateTracer`.
+
+use std::collections::BTreeMap;
+
+use ethnum::U256;
+use evm_loader::evm::tracing::event_listener::trace::{
+    TraceCallConfig, TraceConfig, TracedCall, VMTrace,
+};
+use evm_loader::types::hexbytes::HexBytes;
+use neon_cli_lib::types::Address;
+use serde::Serialize;
+
+use super::{
+    prestate_tracer::{Account, StateChanges, StateOverlay},
+    CallFrame, CallKind, CallTracerConfig, TracerError, TxContext, Word,
+};
+use crate::{data_source::DataSource, types::TraceType};
+
+/// Neon API options sufficient to build any of the parity traces
+pub fn parity_trace_config() -> TraceConfig {
+    TraceConfig {
+        enable_memory: true,
+        disable_storage: true,
+        disable_stack: false,
+        ..TraceConfig::default()
+    }
+}
+
+/// Neon API options of `trace_call` executed on top of `overlay`
+pub fn parity_call_config(overlay: &StateOverlay) -> Result<TraceCallConfig, TracerError> {
+    let mut config = TraceCallConfig {
+        trace_config: parity_trace_config(),
+        ..TraceCallConfig::default()
+    };
+    if !overlay.accounts.is_empty() {
+        let overrides =
+            serde_json::to_value(state_overrides(overlay)).map_err(TracerError::StateOverrides)?;
+        config.state_overrides =
+            Some(serde_json::from_value(overrides).map_err(TracerError::StateOverrides)?);
+    }
+    Ok(config)
+}
+
+/// Account override in the format of the geth `stateOverrides`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct AccountOverride {
+    balance: U256,
+    nonce: u64,
+    code: HexBytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<BTreeMap<Word, Word>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_diff: Option<BTreeMap<Word, Word>>,
+}
+
+fn state_overrides(overlay: &StateOverlay) -> BTreeMap<Address, AccountOverride> {
+    overlay
+        .accounts
+        .iter()
+        .map(|(address, account)| {
+            let storage = account
+                .storage
+                .iter()
+                .map(|(key, value)| (Word(*key), Word(*value)))
+                .collect();
+            // the storage of a destructed account is replaced entirely
+            let (state, state_diff) = if overlay.wiped.contains(address) {
+                (Some(storage), None)
+            } else {
+                (None, Some(storage))
+            };
+
+            let account_override = AccountOverride {
+                balance: account.balance,
+                nonce: account.nonce,
+                code: account.code.clone().into(),
+                state,
+                state_diff,
+            };
+            (*address, account_override)
+        })
+        .collect()
+}
+
+/// Result of `trace_replayTransaction` and `trace_call`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceResults {
+    pub output: HexBytes,
+    pub state_diff: Option<StateDiff>,
+    pub trace: Vec<FlatTrace>,
+    pub vm_trace: Option<VmTrace>,
+}
+
+impl TraceResults {
+    /// Builds the requested traces, `stateDiff` is taken against the state in `overlay`
+    pub async fn new(
+        traced_call: &TracedCall,
+        trace_types: &[TraceType],
+        ctx: &TxContext,
+        overlay: &StateOverlay,
+        source: &DataSource,
+        id: u64,
+    ) -> Result<Self, TracerError> {
+        let changes = if trace_types.contains(&TraceType::StateDiff) {
+            Some(StateChanges::with_overlay(traced_call, ctx, overlay, source, id).await?)
+        } else {
+            None
+        };
+
+        Ok(Self::build(traced_call, trace_types, ctx, changes.as_ref()))
+    }
+
+    /// Builds the requested traces, `stateDiff` is present only if `changes` are provided
+    pub fn build(
+        traced_call: &TracedCall,
+        trace_types: &[TraceType],
+        ctx: &TxContext,
+        changes: Option<&StateChanges>,
+    ) -> Self {
+        let trace = if trace_types.contains(&TraceType::Trace) {
+            let frame = CallFrame::new(traced_call, ctx, &CallTracerConfig::default());
+            flatten(frame)
+        } else {
+            vec![]
+        };
+
+        let vm_trace = trace_types
+            .contains(&TraceType::VmTrace)
+            .then(|| traced_call.vm_trace.as_ref().map(VmTrace::from))
+            .flatten();
+
+        let state_diff = changes
+            .filter(|_| trace_types.contains(&TraceType::StateDiff))
+            .map(state_diff);
+
+        Self {
+            output: traced_call.result.clone().into(),
+            state_diff,
+            trace,
+            vm_trace,
+        }
+    }
+}
+
+/// Entry of `trace_replayBlockTransactions`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceResultsWithTransactionHash {
+    #[serde(flatten)]
+    pub results: TraceResults,
+    pub transaction_hash: Word,
+}
+
+/// Position of a mined transaction, attached to its flat traces
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TxLocation {
+    pub block_hash: Word,
+    pub block_number: u64,
+    pub transaction_hash: Word,
+    pub transaction_position: usize,
+}
+
+impl From<&web3::types::Transaction> for TxLocation {
+    fn from(tx: &web3::types::Transaction) -> Self {
+        Self {
+            block_hash: Word(U256::from_be_bytes(tx.block_hash.unwrap_or_default().0)),
+            block_number: tx.block_number.map_or(0, |number| number.as_u64()),
+            transaction_hash: Word(U256::from_be_bytes(tx.hash.0)),
+            transaction_position: tx.transaction_index.map_or(0, |index| index.as_usize()),
+        }
+    }
+}
+
+/// Flat trace of a mined transaction returned by `trace_transaction`, `trace_get` and `trace_block`
+#[derive(Serialize, Debug, Clone)]
+pub struct LocalizedTrace {
+    #[serde(flatten)]
+    pub trace: FlatTrace,
+    #[serde(flatten)]
+    pub location: TxLocation,
+}
+
+/// Flat traces of the mined transaction
+pub fn localized_traces(
+    traced_call: &TracedCall,
+    ctx: &TxContext,
+    location: &TxLocation,
+) -> Vec<LocalizedTrace> {
+    let frame = CallFrame::new(traced_call, ctx, &CallTracerConfig::default());
+    flatten(frame)
+        .into_iter()
+        .map(|trace| LocalizedTrace {
+            trace,
+            location: location.clone(),
+        })
+        .collect()
+}
+
+/// Single call of the flattened call tree
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FlatTrace {
+    pub action: Action,
+    #[serde(flatten)]
+    pub result: FlatTraceResult,
+    pub subtraces: usize,
+    pub trace_address: Vec<usize>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Action {
+    Call(CallAction),
+    Create(CreateAction),
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallAction {
+    pub call_type: &'static str,
+    pub from: Address,
+    pub gas: U256,
+    pub input: HexBytes,
+    pub to: Address,
+    pub value: U256,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAction {
+    pub from: Address,
+    pub gas: U256,
+    pub init: HexBytes,
+    pub value: U256,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum FlatTraceResult {
+    Result(CallOutput),
+    Error(String),
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CallOutput {
+    Call {
+        #[serde(rename = "gasUsed")]
+        gas_used: U256,
+        output: HexBytes,
+    },
+    Create {
+        address: Address,
+        code: HexBytes,
+        #[serde(rename = "gasUsed")]
+        gas_used: U256,
+    },
+}
+
+/// Flattens the call tree in pre-order, the way OpenEthereum reports the calls
+pub fn flatten(frame: CallFrame) -> Vec<FlatTrace> {
+    let mut traces = vec![];
+    flatten_into(frame, vec![], &mut traces);
+    traces
+}
+
+fn flatten_into(frame: CallFrame, trace_address: Vec<usize>, traces: &mut Vec<FlatTrace>) {
+    let is_create = matches!(frame.kind, CallKind::Create | CallKind::Create2);
+    let value = frame.value.unwrap_or_default();
+    let output = frame.output.unwrap_or_else(|| vec![].into());
+
+    let action = if is_create {
+        Action::Create(CreateAction {
+            from: frame.from,
+            gas: frame.gas,
+            init: frame.input,
+            value,
+        })
+    } else {
+        Action::Call(CallAction {
+            call_type: call_type(frame.kind),
+            from: frame.from,
+            gas: frame.gas,
+            input: frame.input,
+            to: frame.to.unwrap_or_default(),
+            value,
+        })
+    };
+
+    let result = match frame.error {
+        Some(error) => FlatTraceResult::Error(parity_error(&error)),
+        None if is_create => FlatTraceResult::Result(CallOutput::Create {
+            address: frame.to.unwrap_or_default(),
+            code: output,
+            gas_used: frame.gas_used,
+        }),
+        None => FlatTraceResult::Result(CallOutput::Call {
+            gas_used: frame.gas_used,
+            output,
+        }),
+    };
+
+    traces.push(FlatTrace {
+        action,
+        result,
+        subtraces: frame.calls.len(),
+        trace_address: trace_address.clone(),
+        kind: if is_create { "create" } else { "call" },
+    });
+
+    for (idx, call) in frame.calls.into_iter().enumerate() {
+        let mut address = trace_address.clone();
+        address.push(idx);
+        flatten_into(call, address, traces);
+    }
+}
+
+fn call_type(kind: CallKind) -> &'static str {
+    match kind {
+        CallKind::CallCode => "callcode",
+        CallKind::DelegateCall => "delegatecall",
+        CallKind::StaticCall => "staticcall",
+        _ => "call",
+    }
+}
+
+/// Converts the geth error message to the one OpenEthereum reports
+fn parity_error(error: &str) -> String {
+    match error {
+        "execution reverted" => "Reverted".to_string(),
+        "invalid opcode: INVALID" => "Bad instruction".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VmTrace {
+    pub code: HexBytes,
+    pub ops: Vec<VmOperation>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VmOperation {
+    pub pc: usize,
+    pub cost: u64,
+    pub ex: Option<VmExecutedOperation>,
+    pub sub: Option<VmTrace>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VmExecutedOperation {
+    pub used: u64,
+    pub push: Vec<U256>,
+    pub mem: Option<MemoryDiff>,
+    pub store: Option<StorageDiff>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MemoryDiff {
+    pub off: usize,
+    pub data: HexBytes,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StorageDiff {
+    pub key: U256,
+    pub val: U256,
+}
+
+impl From<&VMTrace> for VmTrace {
+    fn from(vm_trace: &VMTrace) -> Self {
+        let mut subs = vm_trace.subs.iter().peekable();
+
+        let ops = vm_trace
+            .operations
+            .iter()
+            .enumerate()
+            .map(|(idx, operation)| VmOperation {
+                pc: operation.pc,
+                cost: operation.gas_cost.as_u64(),
+                ex: operation
+                    .executed
+                    .as_ref()
+                    .map(|executed| VmExecutedOperation {
+                        used: executed.gas_used.as_u64(),
+                        push: executed
+                            .stack_push
+                            .iter()
+                            .map(|word| U256::from_be_bytes(*word))
+                            .collect(),
+                        mem: executed.mem_diff.as_ref().map(|diff| MemoryDiff {
+                            off: diff.offset,
+                            data: diff.data.clone().into(),
+                        }),
+                        store: executed.store_diff.as_ref().map(|diff| StorageDiff {
+                            key: diff.location,
+                            val: diff.value,
+                        }),
+                    }),
+                sub: subs
+                    .next_if(|subtrace| subtrace.parent_step == idx)
+                    .map(Self::from),
+            })
+            .collect();
+
+        Self {
+            code: vm_trace.code.clone().into(),
+            ops,
+        }
+    }
+}
+
+/// Changes of the accounts modified by the transaction
+pub type StateDiff = BTreeMap<Address, AccountDiff>;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountDiff {
+    pub balance: Diff<U256>,
+    pub nonce: Diff<U256>,
+    pub code: Diff<HexBytes>,
+    pub storage: BTreeMap<Word, Diff<Word>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum Diff<T> {
+    #[serde(rename = "=")]
+    Same,
+    #[serde(rename = "+")]
+    Born(T),
+    #[serde(rename = "-")]
+    Died(T),
+    #[serde(rename = "*")]
+    Changed { from: T, to: T },
+}
+
+impl<T: PartialEq> Diff<T> {
+    fn new(from: T, to: T) -> Self {
+        if from == to {
+            Self::Same
+        } else {
+            Self::Changed { from, to }
+        }
+    }
+}
+
+impl<T> Diff<T> {
+    fn is_same(&self) -> bool {
+        matches!(self, Self::Same)
+    }
+}
+
+pub fn state_diff(changes: &StateChanges) -> StateDiff {
+    let empty = Account::default();
+    let mut state_diff = BTreeMap::new();
+
+    for (address, before) in &changes.pre {
+        let after = changes.post.get(address).unwrap_or(&empty);
+        let existed = !before.is_empty();
+        let exists = !changes.deleted.contains(address) && !after.is_empty();
+
+        let account_diff = match (existed, exists) {
+            (false, false) => continue,
+            (false, true) => AccountDiff {
+                balance: Diff::Born(after.balance),
+                nonce: Diff::Born(U256::from(after.nonce)),
+                code: Diff::Born(after.code.clone().into()),
+                storage: after
+                    .storage
+                    .iter()
+                    .filter(|(_, value)| **value != U256::ZERO)
+                    .map(|(key, value)| (Word(*key), Diff::Born(Word(*value))))
+                    .collect(),
+            },
+            (true, false) => AccountDiff {
+                balance: Diff::Died(before.balance),
+                nonce: Diff::Died(U256::from(before.nonce)),
+                code: Diff::Died(before.code.clone().into()),
+                storage: before
+                    .storage
+                    .iter()
+                    .filter(|(_, value)| **value != U256::ZERO)
+                    .map(|(key, value)| (Word(*key), Diff::Died(Word(*value))))
+                    .collect(),
+            },
+            (true, true) => {
+                let account_diff = AccountDiff {
+                    balance: Diff::new(before.balance, after.balance),
+                    nonce: Diff::new(U256::from(before.nonce), U256::from(after.nonce)),
+                    code: if before.code == after.code {
+                        Diff::Same
+                    } else {
+                        Diff::Changed {
+                            from: before.code.clone().into(),
+                            to: after.code.clone().into(),
+                        }
+                    },
+                    storage: before
+                        .storage
+                        .iter()
+                        .filter_map(|(key, value)| {
+                            let new_value = after.storage.get(key).copied().unwrap_or(*value);
+                            let diff = Diff::new(Word(*value), Word(new_value));
+                            (!diff.is_same()).then_some((Word(*key), diff))
+                        })
+                        .collect(),
+                };
+                if account_diff.balance.is_same()
+                    && account_diff.nonce.is_same()
+                    && account_diff.code.is_same()
+                    && account_diff.storage.is_empty()
+                {
+                    continue;
+                }
+                account_diff
+            }
+        };
+
+        state_diff.insert(*address, account_diff);
+    }
+
+    state_diff
+}
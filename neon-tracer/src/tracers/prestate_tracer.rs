@@ -0,0 +1,655 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+};
+
+use ethnum::U256;
+use evm_loader::evm::tracing::event_listener::trace::{FullTraceData, TracedCall, VMOperation};
+use evm_loader::types::hexbytes::HexBytes;
+use futures::future::{try_join, try_join_all};
+use neon_cli_lib::types::Address;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    exit_status_error, frame_error, frame_output, pushed_word, stack_item, walk, word_to_address,
+    CallKind, CallSite, TraceVisitor, TracerError, TxContext, Visit, Word,
+};
+use crate::{
+    api_client::config::MethodClass,
+    data_source::{DataSource, ERR},
+    opcodes,
+    service::Result,
+};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PrestateTracerConfig {
+    /// Return the changes made by the transaction instead of the prestate
+    pub diff_mode: bool,
+}
+
+/// Account state in the geth `prestateTracer` format
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AccountState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<HexBytes>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<Word, Word>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PrestateResult {
+    /// State of every account accessed by the transaction before it ran
+    Prestate(BTreeMap<Address, AccountState>),
+    /// Changed fields of the modified accounts before and after the transaction
+    Diff {
+        pre: BTreeMap<Address, AccountState>,
+        post: BTreeMap<Address, AccountState>,
+    },
+}
+
+impl PrestateResult {
+    pub async fn new(
+        traced_call: &TracedCall,
+        ctx: &TxContext,
+        config: &PrestateTracerConfig,
+        overlay: &StateOverlay,
+        source: &DataSource,
+        id: u64,
+    ) -> std::result::Result<Self, TracerError> {
+        let changes = StateChanges::with_overlay(traced_call, ctx, overlay, source, id).await?;
+
+        if !config.diff_mode {
+            let prestate = changes
+                .pre
+                .iter()
+                .map(|(address, account)| (*address, account.to_state()))
+                .collect();
+            return Ok(Self::Prestate(prestate));
+        }
+
+        let (pre, post) = diff(&changes);
+
+        Ok(Self::Diff { pre, post })
+    }
+}
+
+/// State of the accounts accessed by the transaction before and after it ran
+#[derive(Debug, Clone, Default)]
+pub struct StateChanges {
+    pub pre: BTreeMap<Address, Account>,
+    pub post: BTreeMap<Address, Account>,
+    /// Accounts destructed by the transaction
+    pub deleted: BTreeSet<Address>,
+    /// Contracts created by the transaction
+    pub created: BTreeSet<Address>,
+}
+
+impl StateChanges {
+    /// Collects the changes of a transaction executed on top of `overlay`
+    pub async fn with_overlay(
+        traced_call: &TracedCall,
+        ctx: &TxContext,
+        overlay: &StateOverlay,
+        source: &DataSource,
+        id: u64,
+    ) -> std::result::Result<Self, TracerError> {
+        let access = StateCollector::collect(traced_call, ctx);
+
+        let accounts = try_join_all(
+            access
+                .accounts
+                .iter()
+                .map(|address| load_account(source, *address, &access, ctx.slot, id)),
+        )
+        .await
+        .map_err(TracerError::DataSource)?;
+        let pre: BTreeMap<Address, Account> = access
+            .accounts
+            .iter()
+            .copied()
+            .zip(accounts)
+            .map(|(address, mut account)| {
+                overlay.apply_to(&address, &mut account);
+                (address, account)
+            })
+            .collect();
+        let (post, deleted) = apply(&pre, &access.effects);
+
+        Ok(Self {
+            pre,
+            post,
+            deleted,
+            created: access.created,
+        })
+    }
+}
+
+/// Storage slots written by the transaction with their final values,
+/// the writes of the reverted calls are not included
+pub fn storage_writes(
+    traced_call: &TracedCall,
+    ctx: &TxContext,
+) -> BTreeMap<(Address, U256), U256> {
+    StateCollector::collect(traced_call, ctx)
+        .effects
+        .into_iter()
+        .filter_map(|effect| match effect {
+            Effect::Store {
+                address,
+                key,
+                value,
+            } => Some(((address, key), value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// State left by the previously executed transactions which is not committed to the chain
+#[derive(Debug, Clone, Default)]
+pub struct StateOverlay {
+    pub accounts: BTreeMap<Address, Account>,
+    /// Destructed accounts, their storage is empty apart from the slots in `accounts`
+    pub wiped: BTreeSet<Address>,
+}
+
+impl StateOverlay {
+    fn apply_to(&self, address: &Address, account: &mut Account) {
+        let Some(base) = self.accounts.get(address) else {
+            return;
+        };
+
+        account.balance = base.balance;
+        account.nonce = base.nonce;
+        account.code = base.code.clone();
+        for (key, value) in &mut account.storage {
+            match base.storage.get(key) {
+                Some(base_value) => *value = *base_value,
+                None if self.wiped.contains(address) => *value = U256::ZERO,
+                None => {}
+            }
+        }
+    }
+
+    /// Puts the changes made by a transaction on top of the overlay
+    pub fn update(&mut self, changes: &StateChanges) {
+        for (address, account) in &changes.post {
+            if changes.deleted.contains(address) {
+                self.accounts.insert(*address, Account::default());
+                self.wiped.insert(*address);
+                continue;
+            }
+
+            let base = self.accounts.entry(*address).or_default();
+            base.balance = account.balance;
+            base.nonce = account.nonce;
+            base.code = account.code.clone();
+            base.storage
+                .extend(account.storage.iter().map(|(key, value)| (*key, *value)));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Account {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Vec<u8>,
+    /// Values of the slots accessed by the transaction
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl Account {
+    /// Whether the account is empty in the EIP-161 sense
+    pub fn is_empty(&self) -> bool {
+        self.balance == U256::ZERO && self.nonce == 0 && self.code.is_empty()
+    }
+
+    fn to_state(&self) -> AccountState {
+        AccountState {
+            balance: Some(self.balance),
+            nonce: (self.nonce != 0).then_some(self.nonce),
+            code: (!self.code.is_empty()).then(|| self.code.clone().into()),
+            storage: self
+                .storage
+                .iter()
+                .map(|(key, value)| (Word(*key), Word(*value)))
+                .collect(),
+        }
+    }
+}
+
+/// State change made by a successfully finished call
+#[derive(Debug, Clone)]
+enum Effect {
+    Transfer {
+        from: Address,
+        to: Address,
+        value: U256,
+    },
+    Fee {
+        from: Address,
+        value: U256,
+    },
+    IncrementNonce(Address),
+    SetCode(Address, Vec<u8>),
+    Store {
+        address: Address,
+        key: U256,
+        value: U256,
+    },
+    SelfDestruct {
+        address: Address,
+        beneficiary: Address,
+    },
+}
+
+#[derive(Debug, Default)]
+struct StateAccess {
+    accounts: BTreeSet<Address>,
+    storage: BTreeMap<Address, BTreeSet<U256>>,
+    /// Original values of the slots which were read before the first write
+    loaded: BTreeMap<(Address, U256), U256>,
+    written: BTreeSet<(Address, U256)>,
+    effects: Vec<Effect>,
+    created: BTreeSet<Address>,
+}
+
+/// Collects the accessed state and the changes made by the calls which did not fail
+struct StateCollector {
+    access: StateAccess,
+    /// Open call frames, the top level call first
+    frames: Vec<CallEffects>,
+    /// Frame of the nested call made by the last step, opened by `enter`
+    pending: Option<CallEffects>,
+}
+
+/// Changes made by a call, they take place if the call succeeds
+struct CallEffects {
+    /// Contract the code is executed on behalf of
+    address: Address,
+    effects: Vec<Effect>,
+    success: bool,
+    /// Address of the contract created by the call
+    created: Option<Address>,
+}
+
+impl StateCollector {
+    fn collect(traced_call: &TracedCall, ctx: &TxContext) -> StateAccess {
+        let mut access = StateAccess::default();
+
+        access.accounts.insert(ctx.from);
+        access.effects.push(Effect::IncrementNonce(ctx.from));
+        if let Some(gas_price) = ctx.gas_price {
+            access.effects.push(Effect::Fee {
+                from: ctx.from,
+                value: gas_price.saturating_mul(U256::from(traced_call.used_gas)),
+            });
+        }
+
+        let to = ctx.to.or_else(|| {
+            ctx.nonce
+                .map(|nonce| Address::from_create(&ctx.from, nonce))
+        });
+        let mut effects = vec![];
+        if let Some(to) = to {
+            access.accounts.insert(to);
+            effects.push(Effect::Transfer {
+                from: ctx.from,
+                to,
+                value: ctx.value,
+            });
+            if ctx.to.is_none() {
+                access.created.insert(to);
+                effects.push(Effect::IncrementNonce(to));
+                effects.push(Effect::SetCode(to, traced_call.result.clone()));
+            }
+        }
+
+        let mut collector = Self {
+            access,
+            frames: vec![CallEffects {
+                address: to.unwrap_or_default(),
+                effects,
+                success: true,
+                created: None,
+            }],
+            pending: None,
+        };
+        walk(traced_call, &mut collector).unwrap_or_else(|never| match never {});
+
+        let top = collector.current();
+        if exit_status_error(&traced_call.exit_status).is_none() {
+            let effects = std::mem::take(&mut top.effects);
+            collector.access.effects.extend(effects);
+        }
+
+        collector.access
+    }
+
+    fn current(&mut self) -> &mut CallEffects {
+        self.frames
+            .last_mut()
+            .expect("the top level frame is never closed")
+    }
+
+    /// Opens the frame of the nested call made by the current frame
+    fn call(&mut self, operation: &VMOperation, site: CallSite) -> CallEffects {
+        let address = self.current().address;
+        let is_create = matches!(site.kind, CallKind::Create | CallKind::Create2);
+        let value = site.value.unwrap_or_default();
+        let mut effects = vec![];
+
+        if is_create {
+            self.current().effects.push(Effect::IncrementNonce(address));
+        }
+        if let Some(to) = site.to {
+            self.access.accounts.insert(to);
+            if is_create {
+                self.access.created.insert(to);
+                effects.push(Effect::IncrementNonce(to));
+            }
+            if site.kind == CallKind::Call || is_create {
+                effects.push(Effect::Transfer {
+                    from: address,
+                    to,
+                    value,
+                });
+            }
+        }
+
+        CallEffects {
+            // DELEGATECALL and CALLCODE execute the callee code in the context of the caller
+            address: match site.kind {
+                CallKind::DelegateCall | CallKind::CallCode => address,
+                _ => site.to.unwrap_or_default(),
+            },
+            effects,
+            success: pushed_word(operation) != Some(U256::ZERO),
+            created: site.to.filter(|_| is_create),
+        }
+    }
+
+    /// Applies the effects of the finished nested call, `last` is its last operation
+    fn close(&mut self, mut call: CallEffects, last: Option<&Visit>) {
+        if let Some(last) = last {
+            call.success &= frame_error(last.operation).is_none();
+            if let (Some(to), Some(data)) = (call.created, last.data) {
+                let code = frame_output(last.operation, data).unwrap_or_default();
+                call.effects.push(Effect::SetCode(to, code));
+            }
+        }
+
+        if call.success {
+            self.current().effects.extend(call.effects);
+        }
+    }
+}
+
+impl<'t> TraceVisitor<'t> for StateCollector {
+    type Error = Infallible;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Infallible> {
+        let Some(data) = visit.data else {
+            return Ok(());
+        };
+
+        let frame = self
+            .frames
+            .last_mut()
+            .expect("the top level frame is never closed");
+        self.access
+            .record(visit.operation, data, frame.address, &mut frame.effects);
+
+        if let Some(site) = CallSite::decode(visit.operation, data) {
+            let call = self.call(visit.operation, site);
+            match visit.sub {
+                Some(_) => self.pending = Some(call),
+                // calls of the precompiles and the accounts without code have no nested steps
+                None => self.close(call, None),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self, _visit: &Visit<'t>) -> Result<bool, Infallible> {
+        let Some(call) = self.pending.take() else {
+            return Ok(false);
+        };
+        self.frames.push(call);
+
+        Ok(true)
+    }
+
+    fn exit(&mut self, _visit: &Visit<'t>, last: Option<&Visit<'t>>) -> Result<(), Infallible> {
+        let call = self.frames.pop().expect("the nested call frame is open");
+        self.close(call, last);
+
+        Ok(())
+    }
+}
+
+impl StateAccess {
+    /// Records the state accessed by the operation and its effects
+    fn record(
+        &mut self,
+        operation: &VMOperation,
+        data: &FullTraceData,
+        address: Address,
+        effects: &mut Vec<Effect>,
+    ) {
+        match operation.instruction {
+            opcodes::SLOAD => {
+                let Some(key) = stack_item(data, 0) else {
+                    return;
+                };
+                self.storage.entry(address).or_default().insert(key);
+                if !self.written.contains(&(address, key)) {
+                    if let Some(value) = pushed_word(operation) {
+                        self.loaded.entry((address, key)).or_insert(value);
+                    }
+                }
+            }
+            opcodes::SSTORE => {
+                let (Some(key), Some(value)) = (stack_item(data, 0), stack_item(data, 1)) else {
+                    return;
+                };
+                self.storage.entry(address).or_default().insert(key);
+                self.written.insert((address, key));
+                effects.push(Effect::Store {
+                    address,
+                    key,
+                    value,
+                });
+            }
+            opcodes::BALANCE
+            | opcodes::EXTCODESIZE
+            | opcodes::EXTCODECOPY
+            | opcodes::EXTCODEHASH => {
+                if let Some(word) = stack_item(data, 0) {
+                    self.accounts.insert(word_to_address(word));
+                }
+            }
+            opcodes::SELFDESTRUCT => {
+                if let Some(word) = stack_item(data, 0) {
+                    let beneficiary = word_to_address(word);
+                    self.accounts.insert(beneficiary);
+                    effects.push(Effect::SelfDestruct {
+                        address,
+                        beneficiary,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn load_account(
+    source: &DataSource,
+    address: Address,
+    access: &StateAccess,
+    slot: u64,
+    id: u64,
+) -> Result<Account> {
+    let tout = source.default_timeout(MethodClass::Call);
+    let neon_api = &source.neon_api;
+
+    // the account and the slots not loaded by the transaction itself are read concurrently
+    let keys: Vec<U256> = access
+        .storage
+        .get(&address)
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect();
+    let values = try_join_all(keys.iter().map(|key| async {
+        match access.loaded.get(&(address, *key)) {
+            Some(value) => Ok(*value),
+            None => {
+                neon_api
+                    .get_storage_at(address, *key, slot, &tout, id)
+                    .await
+            }
+        }
+    }));
+    let (account, values) =
+        try_join(neon_api.get_ether_account(address, slot, &tout, id), values).await?;
+
+    let mut result = Account {
+        storage: keys.into_iter().zip(values).collect(),
+        ..Account::default()
+    };
+    if let Some(account) = account {
+        result.balance = U256::from_str_prefixed(&account.balance)
+            .map_err(|_| ERR("cast GetEtherAccountDataReturn.balance->U256", id))?;
+        result.nonce = u64::from(account.trx_count);
+        result.code = hex::decode(account.code.trim_start_matches("0x")).unwrap_or_default();
+    }
+
+    Ok(result)
+}
+
+/// Applies the effects to the prestate, returns the poststate and the destructed accounts
+fn apply(
+    pre: &BTreeMap<Address, Account>,
+    effects: &[Effect],
+) -> (BTreeMap<Address, Account>, BTreeSet<Address>) {
+    let mut post = pre.clone();
+    let mut deleted = BTreeSet::new();
+
+    for effect in effects {
+        match effect {
+            Effect::Transfer { from, to, value } => {
+                let sender = post.entry(*from).or_default();
+                sender.balance = sender.balance.saturating_sub(*value);
+                let recipient = post.entry(*to).or_default();
+                recipient.balance = recipient.balance.saturating_add(*value);
+            }
+            Effect::Fee { from, value } => {
+                let sender = post.entry(*from).or_default();
+                sender.balance = sender.balance.saturating_sub(*value);
+            }
+            Effect::IncrementNonce(address) => {
+                post.entry(*address).or_default().nonce += 1;
+            }
+            Effect::SetCode(address, code) => {
+                post.entry(*address).or_default().code = code.clone();
+            }
+            Effect::Store {
+                address,
+                key,
+                value,
+            } => {
+                post.entry(*address)
+                    .or_default()
+                    .storage
+                    .insert(*key, *value);
+            }
+            Effect::SelfDestruct {
+                address,
+                beneficiary,
+            } => {
+                let balance = std::mem::take(&mut post.entry(*address).or_default().balance);
+                let recipient = post.entry(*beneficiary).or_default();
+                recipient.balance = recipient.balance.saturating_add(balance);
+                deleted.insert(*address);
+            }
+        }
+    }
+
+    (post, deleted)
+}
+
+/// Builds the `diffMode` output the same way geth does: `pre` keeps the full state of
+/// the modified accounts but only the changed slots, `post` holds only the changed fields.
+/// Destructed accounts are present in `pre` only, created ones in `post` only.
+fn diff(
+    changes: &StateChanges,
+) -> (
+    BTreeMap<Address, AccountState>,
+    BTreeMap<Address, AccountState>,
+) {
+    let mut pre_states = BTreeMap::new();
+    let mut post_states = BTreeMap::new();
+
+    for (address, before) in &changes.pre {
+        if changes.deleted.contains(address) {
+            pre_states.insert(*address, before.to_state());
+            continue;
+        }
+        let Some(after) = changes.post.get(address) else {
+            continue;
+        };
+
+        let mut pre_state = before.to_state();
+        pre_state.storage.clear();
+        let mut post_state = AccountState::default();
+        let mut modified = false;
+
+        if after.balance != before.balance {
+            modified = true;
+            post_state.balance = Some(after.balance);
+        }
+        if after.nonce != before.nonce {
+            modified = true;
+            post_state.nonce = Some(after.nonce);
+        }
+        if after.code != before.code {
+            modified = true;
+            post_state.code = Some(after.code.clone().into());
+        }
+
+        for (key, value) in &before.storage {
+            let new_value = after.storage.get(key).copied().unwrap_or(*value);
+            if new_value == *value {
+                continue;
+            }
+            modified = true;
+            if *value != U256::ZERO {
+                pre_state.storage.insert(Word(*key), Word(*value));
+            }
+            if new_value != U256::ZERO {
+                post_state.storage.insert(Word(*key), Word(new_value));
+            }
+        }
+
+        if !modified {
+            continue;
+        }
+        // the account created over an existing one keeps its prestate
+        let existed = !before.is_empty() || before.storage.values().any(|v| *v != U256::ZERO);
+        if !changes.created.contains(address) || existed {
+            pre_states.insert(*address, pre_state);
+        }
+        post_states.insert(*address, post_state);
+    }
+
+    (pre_states, post_states)
+}
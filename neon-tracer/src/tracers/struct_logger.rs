@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+
+use neon_cli_lib::types::Address;
+use serde::Deserialize;
+
+use super::TracerError;
+use crate::{opcodes, types::geth::Step};
+
+/// `tracerConfig` of the default struct logger, selects the steps to return
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct StructLoggerConfig {
+    /// Opcode names, a trailing `*` matches any suffix, e.g. `["SLOAD", "SSTORE", "CALL*"]`
+    pub opcodes: Option<Vec<String>>,
+    pub min_depth: Option<u32>,
+    pub max_depth: Option<u32>,
+    /// Contract the steps are executed on behalf of, the caller for DELEGATECALL and CALLCODE
+    pub address: Option<Address>,
+    /// Number of the matching steps to skip
+    pub offset: usize,
+    /// Maximum number of the steps to return
+    pub limit: Option<usize>,
+    pub format: LogFormat,
+}
+
+/// Encoding of the step snapshots in `structLogs`
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    /// Full stack, memory and storage at every step, as geth does
+    #[default]
+    Full,
+    /// Stack, memory, storage and return data only when they changed since the previous step
+    Delta,
+    /// EIP-3155 JSON lines, the result is a string
+    Eip3155,
+}
+
+/// Default tracer with the compiled `StructLoggerConfig`
+#[derive(Debug, Clone, Default)]
+pub struct StructLogger {
+    pub filter: StepFilter,
+    pub format: LogFormat,
+}
+
+impl StructLogger {
+    pub fn new(config: StructLoggerConfig) -> Result<Self, TracerError> {
+        let format = config.format;
+        Ok(Self {
+            filter: StepFilter::new(config)?,
+            format,
+        })
+    }
+}
+
+/// Steps selected by `StructLoggerConfig`
+#[derive(Debug, Clone, Default)]
+pub struct StepFilter {
+    opcodes: Option<BTreeSet<u8>>,
+    min_depth: Option<u32>,
+    max_depth: Option<u32>,
+    address: Option<Address>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl StepFilter {
+    pub fn new(config: StructLoggerConfig) -> Result<Self, TracerError> {
+        let opcodes = config
+            .opcodes
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .map(|pattern| match opcodes::opcodes_matching(pattern) {
+                        matched if matched.is_empty() => Err(TracerError::InvalidStructLogFilter(
+                            format!("unknown opcode {pattern}"),
+                        )),
+                        matched => Ok(matched),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|matched| matched.into_iter().flatten().collect())
+            })
+            .transpose()?;
+
+        if let (Some(min_depth), Some(max_depth)) = (config.min_depth, config.max_depth) {
+            if min_depth > max_depth {
+                return Err(TracerError::InvalidStructLogFilter(format!(
+                    "minDepth {min_depth} is greater than maxDepth {max_depth}"
+                )));
+            }
+        }
+
+        Ok(Self {
+            opcodes,
+            min_depth: config.min_depth,
+            max_depth: config.max_depth,
+            address: config.address,
+            offset: config.offset,
+            limit: config.limit,
+        })
+    }
+
+    /// Whether the steps are filtered or paged, the total number of the matching steps
+    /// is reported then
+    pub fn is_active(&self) -> bool {
+        self.opcodes.is_some()
+            || self.min_depth.is_some()
+            || self.max_depth.is_some()
+            || self.address.is_some()
+            || self.offset > 0
+            || self.limit.is_some()
+    }
+
+    pub fn matches(&self, step: &Step) -> bool {
+        self.opcodes
+            .as_ref()
+            .map_or(true, |opcodes| opcodes.contains(&step.instruction))
+            && self.min_depth.map_or(true, |depth| step.depth >= depth)
+            && self.max_depth.map_or(true, |depth| step.depth <= depth)
+            && self.address.map_or(true, |address| step.address == address)
+    }
+
+    /// Marks the returned steps, counts the matching ones
+    pub fn select(&self, steps: &[Step]) -> (Vec<bool>, usize) {
+        let mut total = 0;
+        let mut returned = 0;
+        let selected = steps
+            .iter()
+            .map(|step| {
+                if !self.matches(step) {
+                    return false;
+                }
+                total += 1;
+                let selected =
+                    total > self.offset && self.limit.map_or(true, |limit| returned < limit);
+                if selected {
+                    returned += 1;
+                }
+                selected
+            })
+            .collect();
+        (selected, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(instruction: u8, depth: u32, address: u8) -> Step {
+        Step {
+            pc: 0,
+            instruction,
+            gas: 0,
+            gas_cost: 0,
+            depth,
+            address: Address::from([address; 20]),
+            refund: 0,
+            error: None,
+        }
+    }
+
+    fn filter(config: serde_json::Value) -> Result<StepFilter, TracerError> {
+        StepFilter::new(serde_json::from_value(config).unwrap())
+    }
+
+    #[test]
+    fn matches_opcode_patterns() {
+        let filter = filter(serde_json::json!({"opcodes": ["sload", "SSTORE", "CALL*"]})).unwrap();
+        assert!(filter.is_active());
+        assert!(filter.matches(&step(opcodes::SLOAD, 1, 0)));
+        assert!(filter.matches(&step(opcodes::SSTORE, 1, 0)));
+        assert!(filter.matches(&step(opcodes::CALL, 1, 0)));
+        assert!(filter.matches(&step(opcodes::CALLCODE, 1, 0)));
+        assert!(!filter.matches(&step(opcodes::DELEGATECALL, 1, 0)));
+        assert!(!filter.matches(&step(opcodes::STOP, 1, 0)));
+    }
+
+    #[test]
+    fn matches_depth_and_address() {
+        let filter = filter(serde_json::json!({
+            "minDepth": 2,
+            "maxDepth": 3,
+            "address": format!("0x{}", hex::encode([0xaa; 20])),
+        }))
+        .unwrap();
+        assert!(!filter.matches(&step(opcodes::STOP, 1, 0xaa)));
+        assert!(filter.matches(&step(opcodes::STOP, 2, 0xaa)));
+        assert!(filter.matches(&step(opcodes::STOP, 3, 0xaa)));
+        assert!(!filter.matches(&step(opcodes::STOP, 4, 0xaa)));
+        assert!(!filter.matches(&step(opcodes::STOP, 2, 0xbb)));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(matches!(
+            filter(serde_json::json!({"opcodes": ["SLOAD", "NOPE"]})),
+            Err(TracerError::InvalidStructLogFilter(_))
+        ));
+        assert!(matches!(
+            filter(serde_json::json!({"minDepth": 3, "maxDepth": 2})),
+            Err(TracerError::InvalidStructLogFilter(_))
+        ));
+    }
+
+    #[test]
+    fn selects_page_of_matching_steps() {
+        let steps = [
+            step(opcodes::SLOAD, 1, 0),
+            step(opcodes::STOP, 1, 0),
+            step(opcodes::SLOAD, 1, 0),
+            step(opcodes::SLOAD, 1, 0),
+            step(opcodes::SLOAD, 1, 0),
+        ];
+
+        let filter = filter(serde_json::json!({"opcodes": ["SLOAD"], "offset": 1, "limit": 2}));
+        let (selected, total) = filter.unwrap().select(&steps);
+        assert_eq!(selected, [false, false, true, true, false]);
+        assert_eq!(total, 4);
+
+        let unfiltered = StepFilter::default();
+        assert!(!unfiltered.is_active());
+        assert_eq!(unfiltered.select(&steps), (vec![true; 5], 5));
+    }
+}
@@ -0,0 +1,113 @@
+//! Depth-first walk over the `VMTrace` hierarchy shared by the tracers.
+//!
+//! `full_trace_data` holds a snapshot per executed operation in execution order: the operations
+//! of a call interleaved with the operations of its nested calls. `walk` visits them in that
+//! order and keeps the step index, so every tracer reads the right snapshot of an operation.
+
+use evm_loader::evm::tracing::event_listener::trace::{
+    FullTraceData, TracedCall, VMOperation, VMTrace,
+};
+
+use super::{step_count, CallSite};
+
+/// Operation reached by `walk`
+#[derive(Debug, Clone, Copy)]
+pub struct Visit<'t> {
+    /// Index of the step in `full_trace_data`
+    pub step: usize,
+    /// Call depth, 1 for the top level call
+    pub depth: usize,
+    pub operation: &'t VMOperation,
+    /// Snapshot of the step, missing if Neon API has not reported it
+    pub data: Option<&'t FullTraceData>,
+    /// Nested call made by the operation
+    pub sub: Option<&'t VMTrace>,
+}
+
+impl Visit<'_> {
+    /// Arguments of the CALL* or CREATE* operation, not known without the snapshot
+    pub fn call_site(&self) -> Option<CallSite> {
+        self.data
+            .and_then(|data| CallSite::decode(self.operation, data))
+    }
+}
+
+/// Callbacks of `walk`
+pub trait TraceVisitor<'t> {
+    type Error;
+
+    /// Called for every operation in execution order
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Self::Error>;
+
+    /// Called after `step` of the operation which made a nested call,
+    /// the steps of the nested call are skipped if `false` is returned
+    fn enter(&mut self, _visit: &Visit<'t>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    /// Called once the nested call entered at `visit` returns,
+    /// `last` is the last operation executed by the call
+    fn exit(&mut self, _visit: &Visit<'t>, _last: Option<&Visit<'t>>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Walks the operations of the traced call and its nested calls,
+/// returns the last operation of the top level call
+pub fn walk<'t, V: TraceVisitor<'t>>(
+    traced_call: &'t TracedCall,
+    visitor: &mut V,
+) -> Result<Option<Visit<'t>>, V::Error> {
+    let Some(vm_trace) = &traced_call.vm_trace else {
+        return Ok(None);
+    };
+
+    let mut walker = Walker {
+        data: &traced_call.full_trace_data,
+        cursor: 0,
+        visitor,
+    };
+    walker.frame(vm_trace, 1)
+}
+
+struct Walker<'t, 'v, V> {
+    data: &'t [FullTraceData],
+    cursor: usize,
+    visitor: &'v mut V,
+}
+
+impl<'t, V: TraceVisitor<'t>> Walker<'t, '_, V> {
+    fn frame(
+        &mut self,
+        vm_trace: &'t VMTrace,
+        depth: usize,
+    ) -> Result<Option<Visit<'t>>, V::Error> {
+        let mut subs = vm_trace.subs.iter().peekable();
+        let mut last = None;
+
+        for (idx, operation) in vm_trace.operations.iter().enumerate() {
+            let visit = Visit {
+                step: self.cursor,
+                depth,
+                operation,
+                data: self.data.get(self.cursor),
+                sub: subs.next_if(|subtrace| subtrace.parent_step == idx),
+            };
+            self.cursor += 1;
+
+            self.visitor.step(&visit)?;
+            if let Some(sub) = visit.sub {
+                if self.visitor.enter(&visit)? {
+                    let sub_last = self.frame(sub, depth + 1)?;
+                    self.visitor.exit(&visit, sub_last.as_ref())?;
+                } else {
+                    self.cursor += step_count(sub);
+                }
+            }
+
+            last = Some(visit);
+        }
+
+        Ok(last)
+    }
+}
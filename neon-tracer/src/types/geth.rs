@@ -1,14 +1,21 @@
-use std::{collections::BTreeMap, iter};
+use std::{collections::BTreeMap, convert::Infallible};
 
 use ethnum::U256;
 use evm_loader::evm::tracing::event_listener::trace::{
-    TraceConfig, TracedCall, VMOperation, VMTrace,
+    FullTraceData, TraceCallConfig, TraceConfig, TracedCall, VMOperation,
 };
 use evm_loader::types::hexbytes::HexBytes;
 use neon_cli_lib::types::Address;
-use serde::{self, Deserialize, Serialize};
+use serde::{self, ser::SerializeStruct, Deserialize, Serialize, Serializer};
 
-use crate::opcodes::opcode_name;
+use crate::{
+    opcodes::{self, opcode_name},
+    tracers::{
+        decode_revert_reason, exit_status_error, frame_error, is_reverted, pushed_word, stack_item,
+        walk, CallFrame, CallKind, GasProfile, LogFormat, NoopResult, PrestateResult, StructLogger,
+        TraceVisitor, TracerError, TxContext, Visit, Word,
+    },
+};
 
 #[derive(Deserialize, Default, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
@@ -56,11 +63,193 @@ pub struct TraceTransactionOptions {
     pub timeout: Option<String>,
 }
 
+/// `TraceConfig` of debug_traceTransaction and debug_traceBlock* extended with
+/// the tracer specific options which are not forwarded to Neon API
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GethTraceConfig {
+    #[serde(flatten)]
+    pub trace_config: TraceConfig,
+    #[serde(default)]
+    pub tracer_config: Option<serde_json::Value>,
+    /// EVM step limit of every traced transaction, capped by the server maximum
+    #[serde(default)]
+    pub max_steps_to_execute: Option<u64>,
+}
+
+/// `TraceCallConfig` of debug_traceCall extended with the tracer specific options,
+/// the overrides are validated before they are forwarded to Neon API
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GethTraceCallConfig {
+    #[serde(flatten)]
+    pub trace_config: TraceConfig,
+    #[serde(default)]
+    pub block_overrides: Option<BlockOverrides>,
+    #[serde(default)]
+    pub state_overrides: Option<StateOverrides>,
+    #[serde(default)]
+    pub tracer_config: Option<serde_json::Value>,
+    /// EVM step limit of the call, capped by the server maximum
+    #[serde(default)]
+    pub max_steps_to_execute: Option<u64>,
+}
+
+/// geth state override set of `eth_call` and `debug_traceCall`
+pub type StateOverrides = BTreeMap<Address, AccountOverride>;
+
+/// Fields of the account replaced for the duration of the call
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<HexBytes>,
+    /// Replaces the whole storage of the account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<BTreeMap<Word, Word>>,
+    /// Replaces the listed slots only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<BTreeMap<Word, Word>>,
+}
+
+/// geth block overrides of `eth_call` and `debug_traceCall`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct BlockOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_limit: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub random: Option<Word>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_fee: Option<U256>,
+}
+
+/// Neon API options of a call executed with the geth overrides
+pub fn trace_call_config(
+    trace_config: TraceConfig,
+    block_overrides: Option<&BlockOverrides>,
+    state_overrides: Option<&StateOverrides>,
+) -> Result<TraceCallConfig, TracerError> {
+    let mut config = TraceCallConfig {
+        trace_config,
+        ..TraceCallConfig::default()
+    };
+
+    if let Some(block_overrides) = block_overrides {
+        let fields = [
+            ("number", block_overrides.number),
+            ("time", block_overrides.time),
+            ("gasLimit", block_overrides.gas_limit),
+        ];
+        for (name, value) in fields {
+            if value.map_or(false, |value| value > U256::from(u64::MAX)) {
+                return Err(TracerError::InvalidOverrides(format!(
+                    "block {name} exceeds 64 bits"
+                )));
+            }
+        }
+
+        let overrides =
+            serde_json::to_value(block_overrides).map_err(TracerError::StateOverrides)?;
+        config.block_overrides =
+            Some(serde_json::from_value(overrides).map_err(TracerError::StateOverrides)?);
+    }
+
+    if let Some(state_overrides) = state_overrides {
+        for (address, account) in state_overrides {
+            if account.state.is_some() && account.state_diff.is_some() {
+                return Err(TracerError::InvalidOverrides(format!(
+                    "account {address:?} has both 'state' and 'stateDiff'"
+                )));
+            }
+            if account
+                .nonce
+                .map_or(false, |nonce| nonce > U256::from(u64::MAX))
+            {
+                return Err(TracerError::InvalidOverrides(format!(
+                    "nonce of account {address:?} exceeds 64 bits"
+                )));
+            }
+        }
+
+        let overrides =
+            serde_json::to_value(state_overrides).map_err(TracerError::StateOverrides)?;
+        config.state_overrides =
+            Some(serde_json::from_value(overrides).map_err(TracerError::StateOverrides)?);
+    }
+
+    Ok(config)
+}
+
 #[derive(Serialize, Debug, Clone)]
 #[serde(untagged, rename_all = "camelCase")]
 pub enum Trace {
     Logs(ExecutionResult),
-    // JsTrace(serde_json::Value),
+    Eip3155(Eip3155Trace),
+    Call(CallFrame),
+    Prestate(PrestateResult),
+    FourByte(BTreeMap<String, u64>),
+    GasProfile(GasProfile),
+    Js(serde_json::Value),
+    Noop(NoopResult),
+    Mux(BTreeMap<String, Trace>),
+}
+
+/// Trace of a block transaction returned by `debug_traceBlockByNumber` and `debug_traceBlockByHash`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TxTraceResult {
+    /// Missing if the transaction is not known to the proxy yet
+    pub tx_hash: Option<Word>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Trace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TxTraceResult {
+    pub fn new(tx_hash: Option<Word>, trace: std::result::Result<Trace, String>) -> Self {
+        match trace {
+            Ok(trace) => Self {
+                tx_hash,
+                result: Some(trace),
+                error: None,
+            },
+            Err(error) => Self {
+                tx_hash,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Page of the contract storage returned by `debug_storageRangeAt`
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageRangeResult {
+    /// Slots keyed by the keccak hash of the slot key, in the hash order
+    pub storage: BTreeMap<Word, StorageEntry>,
+    /// Hash of the first slot of the next page, `null` for the last page
+    pub next_key: Option<Word>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StorageEntry {
+    pub key: Option<Word>,
+    pub value: Word,
 }
 
 /// `ExecutionResult` groups all structured logs emitted by the EVM
@@ -73,83 +262,144 @@ pub struct ExecutionResult {
     pub failed: bool,
     /// Total used gas but include the refunded gas
     pub gas: u64,
-    /// The data after execution or revert reason
+    /// The data after execution or the revert data
     pub return_value: String,
+    /// Decoded `Error(string)` or `Panic(uint256)` of the reverted execution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    /// Number of the steps matching the structLog filter, reported for filtered or paged logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_steps: Option<usize>,
     /// Logs emitted during execution
-    pub struct_logs: Vec<StructLog>,
+    pub struct_logs: StructLogs,
 }
 
 impl From<TracedCall> for ExecutionResult {
-    // TODO: move to trace_call with merging
     fn from(traced_call: TracedCall) -> Self {
-        let failed = false; // TODO: NDEV-1206, NDEV-1207
+        let options = TraceConfig {
+            disable_stack: true,
+            disable_storage: true,
+            ..TraceConfig::default()
+        };
+        Self::new(
+            traced_call,
+            &options,
+            &TxContext::default(),
+            &StructLogger::default(),
+        )
+    }
+}
+
+impl ExecutionResult {
+    pub fn new(
+        traced_call: TracedCall,
+        options: &TraceConfig,
+        ctx: &TxContext,
+        logger: &StructLogger,
+    ) -> Self {
         let gas = traced_call.used_gas;
-        let return_value = String::new(); // TODO NDEV-1206, NDEV-1207
+        let failed = exit_status_error(&traced_call.exit_status).is_some();
+        let revert_reason = if is_reverted(&traced_call.exit_status) {
+            decode_revert_reason(&traced_call.result)
+        } else {
+            None
+        };
+        let mut steps = Step::collect(&traced_call, ctx);
+        let mut data = traced_call.full_trace_data;
 
-        let struct_logs = match traced_call.vm_trace {
-            Some(vm_trace) => StructLog::from_trace_with_depth(vm_trace, 1).collect(),
-            None => vec![],
+        // the snapshots of the steps left out are dropped right away
+        let total_steps = if logger.filter.is_active() {
+            let (selected, total) = logger.filter.select(&steps);
+            let mut keep = selected.iter();
+            steps.retain(|_| keep.next() == Some(&true));
+            let mut keep = selected.iter();
+            data.retain(|_| keep.next() == Some(&true));
+            Some(total)
+        } else {
+            None
         };
 
         Self {
             failed,
             gas,
-            return_value,
-            struct_logs,
+            return_value: hex::encode(traced_call.result),
+            revert_reason,
+            total_steps,
+            struct_logs: StructLogs {
+                steps,
+                data,
+                options: SnapshotOptions::from(options),
+                format: logger.format,
+            },
         }
     }
 }
 
-impl ExecutionResult {
-    pub fn new(traced_call: TracedCall, options: &TraceConfig) -> Self {
-        let gas = traced_call.used_gas;
+/// Snapshots included into the logs
+#[derive(Debug, Clone, Copy)]
+struct SnapshotOptions {
+    stack: bool,
+    memory: bool,
+    storage: bool,
+    return_data: bool,
+}
 
-        let mut logs: Vec<StructLog> = match traced_call.vm_trace {
-            Some(vm_trace) => StructLog::from_trace_with_depth(vm_trace, 1).collect(),
-            None => vec![],
-        };
+impl From<&TraceConfig> for SnapshotOptions {
+    fn from(config: &TraceConfig) -> Self {
+        Self {
+            stack: !config.disable_stack,
+            memory: config.enable_memory,
+            storage: !config.disable_storage,
+            return_data: config.enable_return_data,
+        }
+    }
+}
 
-        let data = traced_call.full_trace_data;
-        assert_eq!(logs.len(), data.len());
-
-        logs.iter_mut().zip(data.into_iter()).for_each(|(l, d)| {
-            if !options.disable_stack {
-                l.stack = Some(
-                    d.stack
-                        .iter()
-                        .map(|entry| U256::from_be_bytes(*entry))
-                        .collect(),
-                );
-            }
+/// Logs of all steps of the execution. Every `StructLog` is built from `full_trace_data`
+/// only while it is serialized and borrows the snapshots instead of copying them,
+/// so a trace takes about the same memory as the `TracedCall` it comes from.
+#[derive(Debug, Clone)]
+pub struct StructLogs {
+    steps: Vec<Step>,
+    data: Vec<FullTraceData>,
+    options: SnapshotOptions,
+    format: LogFormat,
+}
 
-            if options.enable_memory && !d.memory.is_empty() {
-                l.memory = Some(
-                    d.memory
-                        .chunks(32)
-                        .map(|slice| slice.to_vec().into())
-                        .collect(),
-                );
-            }
+impl StructLogs {
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
 
-            if !options.disable_storage {
-                l.storage = Some(
-                    d.storage
-                        .into_iter()
-                        .map(|(k, v)| (k, U256::from_be_bytes(v)))
-                        .collect(),
-                );
-            }
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
 
-            if options.enable_return_data {
-                l.return_data = d.return_data;
-            }
-        });
+    pub fn iter(&self) -> impl Iterator<Item = StructLog<'_>> {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(idx, step)| StructLog::new(step, self.data.get(idx), self.options))
+    }
 
-        Self {
-            failed: traced_call.exit_status.to_ascii_lowercase() != "succeed",
-            gas,
-            return_value: hex::encode(traced_call.result),
-            struct_logs: logs,
+    /// Logs of the `delta` format, every step is compared with the previous returned one
+    pub fn deltas(&self) -> impl Iterator<Item = DeltaLog<'_>> {
+        self.steps.iter().enumerate().map(|(idx, step)| {
+            let prev = idx.checked_sub(1).and_then(|prev| self.data.get(prev));
+            DeltaLog::new(step, prev, self.data.get(idx), self.options)
+        })
+    }
+}
+
+impl Serialize for StructLogs {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.format {
+            // EIP-3155 lines are rendered by `Eip3155Trace` from the same steps
+            LogFormat::Full | LogFormat::Eip3155 => serializer.collect_seq(self.iter()),
+            LogFormat::Delta => serializer.collect_seq(self.deltas()),
         }
     }
 }
@@ -158,85 +408,800 @@ impl ExecutionResult {
 /// transaction in debug mode
 #[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct StructLog {
+pub struct StructLog<'a> {
     /// Program counter.
     pub pc: u64,
     /// Operation name
     #[serde(rename(serialize = "op"))]
     pub op_name: &'static str,
-    /// Amount of used gas
-    pub gas: Option<u64>,
+    /// Remaining gas before the step
+    pub gas: u64,
     /// Gas cost for this instruction.
     pub gas_cost: u64,
     /// Current depth
     pub depth: u32,
     /// Snapshot of the current memory sate
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub memory: Option<Vec<HexBytes>>, // U256 sized chunks
+    pub memory: Option<Memory<'a>>,
     /// Snapshot of the current stack sate
     #[serde(skip_serializing_if = "Option::is_none")]
-    // pub stack: Option<Vec<[u8; 32]>>,
-    pub stack: Option<Vec<U256>>,
+    pub stack: Option<Stack<'a>>,
     /// Result of the step
-    pub return_data: Option<Vec<u8>>,
+    pub return_data: Option<&'a Vec<u8>>,
     /// Snapshot of the current storage
     #[serde(skip_serializing_if = "Option::is_none")]
-    // pub storage: Option<BTreeMap<U256, [u8; 32]>>,
-    pub storage: Option<BTreeMap<U256, U256>>,
+    pub storage: Option<Storage<'a>>,
+    /// Refund counter before the step
+    #[serde(skip_serializing_if = "is_zero")]
+    pub refund: u64,
+    /// Error of the faulting step
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub error: Option<&'a str>,
 }
 
-impl StructLog {
-    // use boxing bc of the recursive opaque type
-    fn from_trace_with_depth(vm_trace: VMTrace, depth: usize) -> Box<dyn Iterator<Item = Self>> {
-        let operations = vm_trace.operations;
-        let mut subs = vm_trace.subs.into_iter().peekable();
-
-        Box::new(
-            operations
-                .into_iter()
-                .enumerate()
-                .flat_map(move |(idx, operation)| {
-                    let main_op = iter::once((depth, operation).into());
-                    let mut subtrace_iter = None;
-                    if subs
-                        .peek()
-                        .map_or(false, |subtrace| idx == subtrace.parent_step)
-                    {
-                        let subtrace = subs.next().expect("just peeked it");
-                        subtrace_iter = Some(Self::from_trace_with_depth(subtrace, depth + 1));
-                    }
-                    main_op.chain(subtrace_iter.into_iter().flatten())
-                }),
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl<'a> StructLog<'a> {
+    fn new(step: &'a Step, data: Option<&'a FullTraceData>, options: SnapshotOptions) -> Self {
+        Self {
+            pc: step.pc,
+            op_name: opcode_name(step.instruction),
+            gas: step.gas,
+            gas_cost: step.gas_cost,
+            depth: step.depth,
+            memory: data
+                .filter(|d| options.memory && !d.memory.is_empty())
+                .map(|d| Memory(&d.memory)),
+            stack: data.filter(|_| options.stack).map(|d| Stack(&d.stack)),
+            return_data: data
+                .filter(|_| options.return_data)
+                .and_then(|d| d.return_data.as_ref()),
+            storage: data.filter(|_| options.storage).map(Storage),
+            refund: step.refund,
+            error: step.error.as_deref(),
+        }
+    }
+}
+
+/// Memory snapshot serialized in 32 bytes chunks
+#[derive(Debug, Clone, Copy)]
+pub struct Memory<'a>(pub &'a [u8]);
+
+impl Serialize for Memory<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(
+            self.0
+                .chunks(32)
+                .map(|chunk| HexBytes::from(chunk.to_vec())),
+        )
+    }
+}
+
+/// Stack snapshot of the step, the top of the stack is the last item
+#[derive(Debug, Clone, Copy)]
+pub struct Stack<'a>(pub &'a [[u8; 32]]);
+
+impl Serialize for Stack<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|entry| U256::from_be_bytes(*entry)))
+    }
+}
+
+/// Storage snapshot of the step
+#[derive(Debug, Clone, Copy)]
+pub struct Storage<'a>(pub &'a FullTraceData);
+
+impl Serialize for Storage<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            self.0
+                .storage
+                .iter()
+                .map(|(key, value)| (key, U256::from_be_bytes(*value))),
         )
     }
 }
 
-impl From<(usize, VMOperation)> for StructLog {
-    fn from((depth, vm_operation): (usize, VMOperation)) -> Self {
-        let pc = vm_operation.pc as u64;
-        let op_name = opcode_name(vm_operation.instruction);
-        let gas = vm_operation.executed.as_ref().map(|e| e.gas_used.as_u64());
-        let gas_cost = vm_operation.gas_cost.as_u64();
-        let depth = depth as u32;
-        let memory = None;
-        let stack = None;
-        let return_data = None;
-        let storage = None;
-        let error = None;
+/// `ExecutionResult` rendered as EIP-3155 JSON lines: a line per step followed by
+/// the summary line, so that a trace can be compared with the traces of geth `evm t8n`,
+/// evmone or revm. The state root of the summary is not known to the tracer and omitted.
+#[derive(Debug, Clone)]
+pub struct Eip3155Trace(pub ExecutionResult);
+
+impl Serialize for Eip3155Trace {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let logs = &self.0.struct_logs;
+        let summary = Eip3155Summary {
+            output: format!("0x{}", self.0.return_value),
+            gas_used: format!("{:#x}", self.0.gas),
+            pass: !self.0.failed,
+        };
+
+        let mut lines = String::new();
+        for (idx, step) in logs.steps.iter().enumerate() {
+            let line = Eip3155Step::new(step, logs.data.get(idx), logs.options);
+            lines += &serde_json::to_string(&line).map_err(serde::ser::Error::custom)?;
+            lines.push('\n');
+        }
+        lines += &serde_json::to_string(&summary).map_err(serde::ser::Error::custom)?;
+        lines.push('\n');
+
+        serializer.serialize_str(&lines)
+    }
+}
+
+/// Step line of EIP-3155, quantities other than the gas are numbers
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Eip3155Step<'a> {
+    pc: u64,
+    op: u8,
+    gas: String,
+    gas_cost: String,
+    mem_size: usize,
+    stack: Vec<String>,
+    depth: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_data: Option<String>,
+    refund: u64,
+    op_name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+impl<'a> Eip3155Step<'a> {
+    fn new(step: &'a Step, data: Option<&FullTraceData>, options: SnapshotOptions) -> Self {
+        Self {
+            pc: step.pc,
+            op: step.instruction,
+            gas: format!("{:#x}", step.gas),
+            gas_cost: format!("{:#x}", step.gas_cost),
+            mem_size: data.map_or(0, |d| d.memory.len()),
+            stack: data.map_or_else(Vec::new, |d| {
+                d.stack
+                    .iter()
+                    .map(|item| format!("{:#x}", U256::from_be_bytes(*item)))
+                    .collect()
+            }),
+            depth: step.depth,
+            return_data: data
+                .filter(|_| options.return_data)
+                .and_then(|d| d.return_data.as_ref())
+                .map(|return_data| format!("0x{}", hex::encode(return_data))),
+            refund: step.refund,
+            op_name: opcode_name(step.instruction),
+            error: step.error.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Eip3155Summary {
+    output: String,
+    gas_used: String,
+    pass: bool,
+}
+
+/// `StructLog` of the `delta` format: the stack, memory, storage and return data are
+/// reported only when they differ from the previous step, as the changes made since it.
+/// Applying the changes of the steps in order restores the full snapshots.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaLog<'a> {
+    pub pc: u64,
+    #[serde(rename(serialize = "op"))]
+    pub op_name: &'static str,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<StackDelta<'a>>,
+    /// New return data, `null` once it is cleared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_data: Option<Option<&'a Vec<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageDelta<'a>>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub refund: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+impl<'a> DeltaLog<'a> {
+    fn new(
+        step: &'a Step,
+        prev: Option<&'a FullTraceData>,
+        data: Option<&'a FullTraceData>,
+        options: SnapshotOptions,
+    ) -> Self {
+        Self {
+            pc: step.pc,
+            op_name: opcode_name(step.instruction),
+            gas: step.gas,
+            gas_cost: step.gas_cost,
+            depth: step.depth,
+            memory: data
+                .filter(|_| options.memory)
+                .and_then(|d| MemoryDelta::new(prev.map_or(&[], |p| &p.memory), &d.memory)),
+            stack: data
+                .filter(|_| options.stack)
+                .and_then(|d| StackDelta::new(prev.map_or(&[], |p| &p.stack), &d.stack)),
+            return_data: data
+                .filter(|_| options.return_data)
+                .map(|d| d.return_data.as_ref())
+                .filter(|return_data| *return_data != prev.and_then(|p| p.return_data.as_ref())),
+            storage: data
+                .filter(|_| options.storage)
+                .and_then(|d| StorageDelta::new(prev, d)),
+            refund: step.refund,
+            error: step.error.as_deref(),
+        }
+    }
+}
+
+/// Stack changes: the number of the items removed from the top, then the items pushed
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct StackDelta<'a> {
+    pub pop: usize,
+    pub push: Stack<'a>,
+}
+
+impl<'a> StackDelta<'a> {
+    fn new(prev: &[[u8; 32]], stack: &'a [[u8; 32]]) -> Option<Self> {
+        let kept = prev
+            .iter()
+            .zip(stack)
+            .take_while(|(prev, item)| prev == item)
+            .count();
+        let delta = Self {
+            pop: prev.len() - kept,
+            push: Stack(&stack[kept..]),
+        };
+        (delta.pop > 0 || !delta.push.0.is_empty()).then_some(delta)
+    }
+}
+
+/// Memory changes: the new memory size and the written ranges of 32 bytes words
+#[derive(Serialize, Debug, Clone)]
+pub struct MemoryDelta {
+    pub size: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub writes: Vec<MemoryWrite>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MemoryWrite {
+    pub offset: usize,
+    pub data: HexBytes,
+}
+
+impl MemoryDelta {
+    fn new(prev: &[u8], memory: &[u8]) -> Option<Self> {
+        if prev == memory {
+            return None;
+        }
+
+        let mut writes: Vec<MemoryWrite> = vec![];
+        let mut last_end = None;
+        for (idx, word) in memory.chunks(32).enumerate() {
+            let offset = idx * 32;
+            if prev.get(offset..offset + word.len()) == Some(word) {
+                continue;
+            }
+            match writes.last_mut() {
+                Some(write) if last_end == Some(offset) => write.data.0.extend_from_slice(word),
+                _ => writes.push(MemoryWrite {
+                    offset,
+                    data: word.to_vec().into(),
+                }),
+            }
+            last_end = Some(offset + word.len());
+        }
+
+        Some(Self {
+            size: memory.len(),
+            writes,
+        })
+    }
+}
+
+/// Storage changes: the slots set to a new value and the slots no longer reported
+#[derive(Debug, Clone, Copy)]
+pub struct StorageDelta<'a> {
+    prev: Option<&'a FullTraceData>,
+    data: &'a FullTraceData,
+}
+
+impl<'a> StorageDelta<'a> {
+    fn new(prev: Option<&'a FullTraceData>, data: &'a FullTraceData) -> Option<Self> {
+        let changed = prev.map_or(!data.storage.is_empty(), |p| p.storage != data.storage);
+        changed.then_some(Self { prev, data })
+    }
+}
+
+impl Serialize for StorageDelta<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let set: BTreeMap<_, _> = self
+            .data
+            .storage
+            .iter()
+            .filter(|(key, value)| self.prev.and_then(|p| p.storage.get(key)) != Some(value))
+            .map(|(key, value)| (key, U256::from_be_bytes(*value)))
+            .collect();
+        let clear: Vec<_> = self
+            .prev
+            .into_iter()
+            .flat_map(|p| p.storage.keys())
+            .filter(|key| !self.data.storage.contains_key(key))
+            .collect();
+
+        let mut state = serializer.serialize_struct("StorageDelta", 2)?;
+        state.serialize_field("set", &set)?;
+        state.serialize_field("clear", &clear)?;
+        state.end()
+    }
+}
+
+/// Step of the execution without the snapshots, a few dozen bytes per step
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub pc: u64,
+    pub instruction: u8,
+    /// Remaining gas before the step
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: u32,
+    /// Contract the step is executed on behalf of
+    pub address: Address,
+    /// Refund counter before the step
+    pub refund: u64,
+    pub error: Option<String>,
+}
+
+impl Step {
+    /// Builds the steps of all operations of the trace including nested calls, depth first,
+    /// in the same order as `full_trace_data`
+    pub fn collect(traced_call: &TracedCall, ctx: &TxContext) -> Vec<Self> {
+        // the gas limit is not known without the transaction, the used gas is the lower bound
+        let gas_limit = ctx.gas.unwrap_or(traced_call.used_gas);
+        let gas = gas_limit.saturating_sub(ctx.intrinsic_gas());
+        let mut replay = GasReplay {
+            frames: vec![GasFrame {
+                address: ctx.to.unwrap_or_default(),
+                gas,
+                remaining: gas,
+                checkpoint: (0, BTreeMap::new()),
+            }],
+            refund: 0,
+            storage: BTreeMap::new(),
+            steps: Vec::with_capacity(traced_call.full_trace_data.len()),
+        };
+        walk(traced_call, &mut replay).unwrap_or_else(|never| match never {});
+
+        // errors other than REVERT stop the execution at the last step
+        let mut steps = replay.steps;
+        if let Some(last) = steps.last_mut() {
+            if last.error.is_none() && last.instruction != opcodes::REVERT {
+                last.error = exit_status_error(&traced_call.exit_status);
+            }
+        }
+        steps
+    }
 
+    fn new(depth: usize, address: Address, operation: &VMOperation, gas: u64, refund: u64) -> Self {
         Self {
+            pc: operation.pc as u64,
+            instruction: operation.instruction,
+            gas,
+            gas_cost: to_u64(operation.gas_cost),
+            depth: depth as u32,
+            address,
+            refund,
+            error: None,
+        }
+    }
+}
+
+fn to_u64(value: U256) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+/// EIP-3529 refund of clearing a storage slot
+const SSTORE_CLEARS_SCHEDULE: u64 = 4_800;
+const WARM_STORAGE_READ_COST: u64 = 100;
+const SSTORE_SET_GAS: u64 = 20_000;
+const SSTORE_RESET_GAS: u64 = 2_900;
+
+/// Original and current value of a storage slot
+#[derive(Clone, Copy)]
+struct SlotValues {
+    /// Unknown if the slot was written before it was ever read
+    original: Option<U256>,
+    current: U256,
+}
+
+/// Replays the remaining gas and the refund counter step by step, the way geth reports them.
+/// Neon API provides the gas cost of every step, the gas available to a nested call is
+/// decoded from the stack and capped by the 63/64 rule.
+struct GasReplay {
+    /// Open call frames, the top level call first
+    frames: Vec<GasFrame>,
+    refund: u64,
+    storage: BTreeMap<(Address, U256), SlotValues>,
+    steps: Vec<Step>,
+}
+
+struct GasFrame {
+    /// Contract the code is executed on behalf of
+    address: Address,
+    gas: u64,
+    remaining: u64,
+    /// Refund counter and storage restored if the call fails
+    checkpoint: (u64, BTreeMap<(Address, U256), SlotValues>),
+}
+
+impl<'t> TraceVisitor<'t> for GasReplay {
+    type Error = Infallible;
+
+    fn step(&mut self, visit: &Visit<'t>) -> Result<(), Infallible> {
+        let operation = visit.operation;
+        let frame = self.current();
+        let (address, remaining) = (frame.address, frame.remaining);
+
+        let cost = to_u64(operation.gas_cost);
+        let mut step = Step::new(visit.depth, address, operation, remaining, self.refund);
+        if operation.instruction == opcodes::INVALID {
+            step.error = frame_error(operation);
+        } else if remaining < cost {
+            step.error = Some("out of gas".to_string());
+        }
+        self.steps.push(step);
+
+        match operation.instruction {
+            opcodes::SLOAD => self.load(operation, visit.data, address),
+            opcodes::SSTORE => self.store(visit.data, address),
+            _ => {}
+        }
+        self.current().remaining = remaining.saturating_sub(cost);
+
+        Ok(())
+    }
+
+    fn enter(&mut self, visit: &Visit<'t>) -> Result<bool, Infallible> {
+        let frame = self.current();
+        let (address, remaining) = (frame.address, frame.remaining);
+
+        let site = visit.call_site();
+        let callee = match site.as_ref().map(|site| (site.kind, site.to)) {
+            Some((CallKind::DelegateCall | CallKind::CallCode, _)) => address,
+            Some((_, Some(to))) => to,
+            _ => Address::default(),
+        };
+
+        let available = remaining - remaining / 64;
+        let gas = site
+            .and_then(|site| site.gas)
+            .map_or(available, |gas| to_u64(gas).min(available));
+
+        let checkpoint = (self.refund, self.storage.clone());
+        self.frames.push(GasFrame {
+            address: callee,
+            gas,
+            remaining: gas,
+            checkpoint,
+        });
+
+        Ok(true)
+    }
+
+    fn exit(&mut self, visit: &Visit<'t>, _last: Option<&Visit<'t>>) -> Result<(), Infallible> {
+        let callee = self.frames.pop().expect("the nested call frame is open");
+        self.current().remaining -= callee.gas.saturating_sub(callee.remaining);
+
+        // the state changes of the failed calls are reverted together with their refunds
+        if pushed_word(visit.operation) == Some(U256::ZERO) {
+            (self.refund, self.storage) = callee.checkpoint;
+        }
+
+        Ok(())
+    }
+}
+
+impl GasReplay {
+    fn current(&mut self) -> &mut GasFrame {
+        self.frames
+            .last_mut()
+            .expect("the top level frame is never closed")
+    }
+
+    fn load(&mut self, operation: &VMOperation, data: Option<&FullTraceData>, address: Address) {
+        let (Some(key), Some(value)) = (data.and_then(|d| stack_item(d, 0)), pushed_word(operation))
+        else {
+            return;
+        };
+        self.storage.entry((address, key)).or_insert(SlotValues {
+            original: Some(value),
+            current: value,
+        });
+    }
+
+    /// Applies the EIP-2200 refund rules with the EIP-3529 constants
+    fn store(&mut self, data: Option<&FullTraceData>, address: Address) {
+        let (Some(key), Some(new)) = (
+            data.and_then(|d| stack_item(d, 0)),
+            data.and_then(|d| stack_item(d, 1)),
+        ) else {
+            return;
+        };
+
+        let slot = self.storage.entry((address, key)).or_insert(SlotValues {
+            original: None,
+            current: new,
+        });
+        let (Some(original), current) = (slot.original, slot.current) else {
+            slot.current = new;
+            return;
+        };
+        slot.current = new;
+        if current == new {
+            return;
+        }
+
+        let mut refund = i128::from(self.refund);
+        if original == current {
+            if original != U256::ZERO && new == U256::ZERO {
+                refund += i128::from(SSTORE_CLEARS_SCHEDULE);
+            }
+        } else {
+            if original != U256::ZERO {
+                if current == U256::ZERO {
+                    refund -= i128::from(SSTORE_CLEARS_SCHEDULE);
+                } else if new == U256::ZERO {
+                    refund += i128::from(SSTORE_CLEARS_SCHEDULE);
+                }
+            }
+            if original == new {
+                refund += i128::from(if original == U256::ZERO {
+                    SSTORE_SET_GAS - WARM_STORAGE_READ_COST
+                } else {
+                    SSTORE_RESET_GAS - WARM_STORAGE_READ_COST
+                });
+            }
+        }
+        self.refund = u64::try_from(refund.max(0)).unwrap_or(u64::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::struct_log_delta;
+
+    fn word(value: u8) -> [u8; 32] {
+        U256::from(value).to_be_bytes()
+    }
+
+    fn step(pc: u64, instruction: u8, gas: u64) -> Step {
+        Step {
             pc,
-            op_name,
+            instruction,
             gas,
-            gas_cost,
-            depth,
-            memory,
-            stack,
-            return_data,
-            storage,
-            error,
+            gas_cost: 3,
+            depth: 1,
+            address: Address::default(),
+            refund: 0,
+            error: None,
+        }
+    }
+
+    fn snapshot(
+        stack: &[u8],
+        memory: &[u8],
+        storage: &[(u8, u8)],
+        return_data: Option<&[u8]>,
+    ) -> FullTraceData {
+        FullTraceData {
+            stack: stack.iter().map(|item| word(*item)).collect(),
+            memory: memory.to_vec(),
+            storage: storage
+                .iter()
+                .map(|(key, value)| (U256::from(*key), word(*value)))
+                .collect(),
+            return_data: return_data.map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Steps pushing and popping the stack, growing and overwriting the memory,
+    /// setting and clearing the storage and the return data
+    fn struct_logs(format: LogFormat) -> StructLogs {
+        let mut failed = step(5, opcodes::REVERT, 88);
+        failed.refund = 4800;
+        failed.error = Some("execution reverted".to_string());
+
+        StructLogs {
+            steps: vec![
+                step(0, 0x60, 100),
+                step(2, 0x60, 97),
+                step(4, 0x52, 94),
+                step(5, opcodes::SSTORE, 91),
+                failed,
+            ],
+            data: vec![
+                snapshot(&[], &[], &[], None),
+                snapshot(&[1], &[], &[], None),
+                snapshot(&[1, 2], &[0xaa; 32], &[(1, 5)], None),
+                snapshot(
+                    &[3],
+                    &[[0xaa; 32], [0xbb; 32]].concat(),
+                    &[(1, 6), (2, 7)],
+                    Some(&[1, 2]),
+                ),
+                snapshot(&[], &[[0xaa; 32], [0xcc; 32]].concat(), &[(2, 7)], None),
+            ],
+            options: SnapshotOptions {
+                stack: true,
+                memory: true,
+                storage: true,
+                return_data: true,
+            },
+            format,
+        }
+    }
+
+    #[test]
+    fn delta_logs_decode_to_full_logs() {
+        let full = serde_json::to_value(struct_logs(LogFormat::Full)).unwrap();
+        let full = full.as_array().unwrap();
+        let delta: Vec<struct_log_delta::DeltaLog> =
+            serde_json::to_value(struct_logs(LogFormat::Delta))
+                .and_then(serde_json::from_value)
+                .unwrap();
+        let snapshots = struct_log_delta::decode(&delta);
+        assert_eq!(snapshots.len(), full.len());
+
+        for ((log, delta), snapshot) in full.iter().zip(&delta).zip(&snapshots) {
+            assert_eq!(log["pc"], delta.pc);
+            assert_eq!(log["op"], delta.op.as_str());
+            assert_eq!(log["gas"], delta.gas);
+            assert_eq!(log["gasCost"], delta.gas_cost);
+            assert_eq!(log["depth"], delta.depth);
+            assert_eq!(
+                log.get("refund").map_or(0, |r| r.as_u64().unwrap()),
+                delta.refund
+            );
+            assert_eq!(
+                log.get("error").and_then(|e| e.as_str()),
+                delta.error.as_deref()
+            );
+
+            let memory = if snapshot.memory.is_empty() {
+                serde_json::Value::Null
+            } else {
+                serde_json::to_value(Memory(&snapshot.memory)).unwrap()
+            };
+            assert_eq!(log.get("memory").cloned().unwrap_or_default(), memory);
+            assert_eq!(log["stack"], serde_json::to_value(&snapshot.stack).unwrap());
+            assert_eq!(
+                log["storage"],
+                serde_json::to_value(&snapshot.storage).unwrap()
+            );
+            assert_eq!(
+                log["returnData"],
+                serde_json::to_value(&snapshot.return_data).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn eip3155_trace_has_line_per_step_and_summary() {
+        let trace = Eip3155Trace(ExecutionResult {
+            failed: true,
+            gas: 21_015,
+            return_value: String::new(),
+            revert_reason: None,
+            total_steps: None,
+            struct_logs: struct_logs(LogFormat::Eip3155),
+        });
+        let lines = serde_json::to_value(trace).unwrap();
+        let lines: Vec<serde_json::Value> = lines
+            .as_str()
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+
+        assert_eq!(
+            lines[0],
+            serde_json::json!({
+                "pc": 0, "op": 0x60, "gas": "0x64", "gasCost": "0x3", "memSize": 0, "stack": [],
+                "depth": 1, "refund": 0, "opName": "PUSH1",
+            })
+        );
+        assert_eq!(
+            lines[3],
+            serde_json::json!({
+                "pc": 5, "op": 0x55, "gas": "0x5b", "gasCost": "0x3", "memSize": 64,
+                "stack": ["0x3"], "depth": 1, "returnData": "0x0102", "refund": 0,
+                "opName": "SSTORE",
+            })
+        );
+        assert_eq!(lines[4]["refund"], 4800);
+        assert_eq!(lines[4]["error"], "execution reverted");
+        assert_eq!(
+            lines[5],
+            serde_json::json!({"output": "0x", "gasUsed": "0x5217", "pass": false})
+        );
+    }
+
+    fn replay(storage: &[(u8, u8)]) -> GasReplay {
+        GasReplay {
+            frames: vec![],
+            refund: 0,
+            storage: storage
+                .iter()
+                .map(|(key, value)| {
+                    let value = U256::from(*value);
+                    let slot = SlotValues {
+                        original: Some(value),
+                        current: value,
+                    };
+                    ((Address::default(), U256::from(*key)), slot)
+                })
+                .collect(),
+            steps: vec![],
         }
     }
+
+    fn sstore(replay: &mut GasReplay, key: u8, value: u8) -> u64 {
+        let data = snapshot(&[value, key], &[], &[], None);
+        replay.store(Some(&data), Address::default());
+        replay.refund
+    }
+
+    #[test]
+    fn clearing_slot_is_refunded() {
+        let mut replay = replay(&[(1, 5)]);
+        assert_eq!(sstore(&mut replay, 1, 0), SSTORE_CLEARS_SCHEDULE);
+        // clearing the slot again changes nothing
+        assert_eq!(sstore(&mut replay, 1, 0), SSTORE_CLEARS_SCHEDULE);
+    }
+
+    #[test]
+    fn restoring_original_value_is_refunded() {
+        let mut replay = replay(&[(1, 0), (2, 5)]);
+        assert_eq!(sstore(&mut replay, 1, 7), 0);
+        assert_eq!(sstore(&mut replay, 1, 0), 19_900);
+
+        // the refund of clearing the slot is taken back once the slot is restored
+        assert_eq!(sstore(&mut replay, 2, 0), 19_900 + 4_800);
+        assert_eq!(sstore(&mut replay, 2, 5), 19_900 + 2_800);
+    }
+
+    #[test]
+    fn slot_written_before_read_is_not_refunded() {
+        let mut replay = replay(&[]);
+        assert_eq!(sstore(&mut replay, 1, 7), 0);
+        assert_eq!(sstore(&mut replay, 1, 0), 0);
+    }
 }
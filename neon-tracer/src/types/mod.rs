@@ -1,4 +1,7 @@
 pub mod geth;
 mod open_eth;
+pub mod raw_transaction;
+#[cfg(test)]
+mod struct_log_delta;
 
 pub use open_eth::*;
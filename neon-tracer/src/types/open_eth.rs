@@ -1,4 +1,5 @@
 use ethnum::U256;
+use neon_cli_lib::types::Address;
 use serde::{
     de::Error, de::MapAccess, de::Visitor, Deserialize, Deserializer, Serialize, Serializer,
 };
@@ -141,3 +142,61 @@ impl<'a> Visitor<'a> for BlockNumberVisitor {
         self.visit_str(value.as_ref())
     }
 }
+
+/// Trace kinds of the OpenEthereum `trace_*` methods
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceType {
+    /// Flattened call tree
+    Trace,
+    /// Executed operations with their effects
+    VmTrace,
+    /// Changes of the modified accounts
+    StateDiff,
+}
+
+/// Trace address of `trace_get`, a number or a hex string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceIndex(pub usize);
+
+impl<'a> Deserialize<'a> for TraceIndex {
+    fn deserialize<D>(deserializer: D) -> Result<TraceIndex, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Index {
+            Num(usize),
+            Hex(String),
+        }
+
+        match Index::deserialize(deserializer)? {
+            Index::Num(index) => Ok(TraceIndex(index)),
+            Index::Hex(value) => {
+                let value = value
+                    .strip_prefix("0x")
+                    .ok_or_else(|| Error::custom("Invalid trace index: missing 0x prefix"))?;
+                usize::from_str_radix(value, 16)
+                    .map(TraceIndex)
+                    .map_err(|e| Error::custom(format!("Invalid trace index: {e}")))
+            }
+        }
+    }
+}
+
+/// Filter of `trace_filter`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TraceFilter {
+    pub from_block: Option<BlockNumber>,
+    pub to_block: Option<BlockNumber>,
+    /// Calls made by any of the addresses, all calls if empty
+    pub from_address: Option<Vec<Address>>,
+    /// Calls made to any of the addresses, all calls if empty
+    pub to_address: Option<Vec<Address>>,
+    /// Number of the matching traces to skip
+    pub after: Option<u64>,
+    /// Maximal number of the traces to return
+    pub count: Option<u64>,
+}
@@ -0,0 +1,292 @@
+use ethnum::U256;
+use neon_cli_lib::types::Address;
+use rlp::{Rlp, RlpStream};
+use thiserror::Error;
+use web3::signing::{keccak256, recover};
+
+const ACCESS_LIST_TX_TYPE: u8 = 0x01;
+const DYNAMIC_FEE_TX_TYPE: u8 = 0x02;
+
+#[derive(Error, Debug)]
+pub enum RawTransactionError {
+    #[error("RLP error: {0}")]
+    Rlp(#[from] rlp::DecoderError),
+
+    #[error("Unsupported transaction type: {0:#04x}")]
+    UnsupportedType(u8),
+
+    #[error("Invalid field {0}")]
+    InvalidField(&'static str),
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+}
+
+/// Signed transaction decoded from its RLP encoding: legacy, EIP-2930 or EIP-1559
+#[derive(Debug, Clone)]
+pub struct RawTransaction {
+    /// Missing for legacy transactions signed without EIP-155 replay protection
+    pub chain_id: Option<u64>,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub value: U256,
+    pub input: Vec<u8>,
+    pub gas: u64,
+    /// `maxFeePerGas` of EIP-1559 transactions
+    pub gas_price: U256,
+    pub nonce: u64,
+}
+
+/// Positions of the transaction fields in the RLP list
+struct Layout {
+    chain_id: Option<usize>,
+    nonce: usize,
+    gas_price: usize,
+    gas: usize,
+    to: usize,
+    value: usize,
+    input: usize,
+    /// Number of the fields covered by the signature
+    signed: usize,
+}
+
+impl RawTransaction {
+    pub fn decode(bytes: &[u8]) -> Result<Self, RawTransactionError> {
+        let (tx_type, payload) = match bytes.first() {
+            // RLP list prefix, legacy transaction
+            Some(byte) if *byte >= 0xc0 => (None, bytes),
+            Some(&tx_type) => (Some(tx_type), &bytes[1..]),
+            None => return Err(rlp::DecoderError::RlpIsTooShort.into()),
+        };
+
+        let rlp = Rlp::new(payload);
+        let (layout, v, r, s) = match tx_type {
+            None => (
+                Layout {
+                    chain_id: None,
+                    nonce: 0,
+                    gas_price: 1,
+                    gas: 2,
+                    to: 3,
+                    value: 4,
+                    input: 5,
+                    signed: 6,
+                },
+                6,
+                7,
+                8,
+            ),
+            Some(ACCESS_LIST_TX_TYPE) => (
+                Layout {
+                    chain_id: Some(0),
+                    nonce: 1,
+                    gas_price: 2,
+                    gas: 3,
+                    to: 4,
+                    value: 5,
+                    input: 6,
+                    signed: 8,
+                },
+                8,
+                9,
+                10,
+            ),
+            Some(DYNAMIC_FEE_TX_TYPE) => (
+                Layout {
+                    chain_id: Some(0),
+                    nonce: 1,
+                    gas_price: 3,
+                    gas: 4,
+                    to: 5,
+                    value: 6,
+                    input: 7,
+                    signed: 9,
+                },
+                9,
+                10,
+                11,
+            ),
+            Some(other) => return Err(RawTransactionError::UnsupportedType(other)),
+        };
+        if rlp.item_count()? != s + 1 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen.into());
+        }
+
+        let v = word(&rlp, v, "v")?;
+        let (chain_id, recovery_id) = match layout.chain_id {
+            Some(position) => (Some(u64_field(&rlp, position, "chainId")?), v),
+            // EIP-155: v = chainId * 2 + 35 + recoveryId
+            None if v >= U256::from(35_u64) => {
+                let v = v - U256::from(35_u64);
+                let two = U256::from(2_u64);
+                let chain_id =
+                    u64::try_from(v / two).map_err(|_| RawTransactionError::InvalidField("v"))?;
+                (Some(chain_id), v % two)
+            }
+            None => (None, v.saturating_sub(U256::from(27_u64))),
+        };
+        let recovery_id = match u8::try_from(recovery_id) {
+            Ok(recovery_id @ (0 | 1)) => i32::from(recovery_id),
+            _ => return Err(RawTransactionError::InvalidSignature),
+        };
+
+        let mut stream = RlpStream::new();
+        match (tx_type, chain_id) {
+            (None, Some(chain_id)) => {
+                stream.begin_list(layout.signed + 3);
+                append_fields(&mut stream, &rlp, layout.signed)?;
+                stream.append(&chain_id);
+                stream.append_empty_data();
+                stream.append_empty_data();
+            }
+            _ => {
+                stream.begin_list(layout.signed);
+                append_fields(&mut stream, &rlp, layout.signed)?;
+            }
+        }
+        let mut message = tx_type.map(|t| vec![t]).unwrap_or_default();
+        message.extend_from_slice(&stream.out());
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&word(&rlp, r, "r")?.to_be_bytes());
+        signature[32..].copy_from_slice(&word(&rlp, s, "s")?.to_be_bytes());
+        let from = recover(&keccak256(&message), &signature, recovery_id)
+            .map_err(|_| RawTransactionError::InvalidSignature)?;
+
+        let to = rlp.at(layout.to)?.data()?;
+        let to = match to.len() {
+            0 => None,
+            20 => Some(Address::from(*arrayref::array_ref![to, 0, 20])),
+            _ => return Err(RawTransactionError::InvalidField("to")),
+        };
+
+        Ok(Self {
+            chain_id,
+            from: Address::from(from.0),
+            to,
+            value: word(&rlp, layout.value, "value")?,
+            input: rlp.at(layout.input)?.data()?.to_vec(),
+            gas: u64_field(&rlp, layout.gas, "gas")?,
+            gas_price: word(&rlp, layout.gas_price, "gasPrice")?,
+            nonce: u64_field(&rlp, layout.nonce, "nonce")?,
+        })
+    }
+}
+
+fn append_fields(
+    stream: &mut RlpStream,
+    rlp: &Rlp,
+    count: usize,
+) -> Result<(), RawTransactionError> {
+    for position in 0..count {
+        stream.append_raw(rlp.at(position)?.as_raw(), 1);
+    }
+    Ok(())
+}
+
+/// Decodes a big-endian integer of at most 32 bytes
+fn word(rlp: &Rlp, position: usize, name: &'static str) -> Result<U256, RawTransactionError> {
+    let data = rlp.at(position)?.data()?;
+    if data.len() > 32 {
+        return Err(RawTransactionError::InvalidField(name));
+    }
+
+    let mut bytes = [0u8; 32];
+    bytes[32 - data.len()..].copy_from_slice(data);
+    Ok(U256::from_be_bytes(bytes))
+}
+
+fn u64_field(rlp: &Rlp, position: usize, name: &'static str) -> Result<u64, RawTransactionError> {
+    u64::try_from(word(rlp, position, name)?).map_err(|_| RawTransactionError::InvalidField(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(hex: &str) -> Result<RawTransaction, RawTransactionError> {
+        RawTransaction::decode(&hex::decode(hex).unwrap())
+    }
+
+    fn address(hex: &str) -> Address {
+        Address::from(*arrayref::array_ref![hex::decode(hex).unwrap(), 0, 20])
+    }
+
+    /// Signed by the `0x4646..46` key
+    fn sender() -> Address {
+        address("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f")
+    }
+
+    #[test]
+    fn decodes_eip155_example() {
+        // the example from the EIP-155 specification
+        let tx = decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
+
+        assert_eq!(tx.chain_id, Some(1));
+        assert_eq!(tx.from, sender());
+        assert_eq!(tx.to, Some(address(&"35".repeat(20))));
+        assert_eq!(tx.value, U256::from(1_000_000_000_000_000_000_u64));
+        assert_eq!(tx.gas, 21_000);
+        assert_eq!(tx.gas_price, U256::from(20_000_000_000_u64));
+        assert_eq!(tx.nonce, 9);
+        assert!(tx.input.is_empty());
+    }
+
+    #[test]
+    fn decodes_eip155_call() {
+        let tx = decode("f86901843b9aca008252089435353535353535353535353535353535353535358084a9059cbb820102a02e43be7a12916cf6f312a513fcb6c98b708ce2dd18dc4ebf72a807c9c8a31b0da02a6583dafe2875ae77148625de50da80236d58c3b002fcd6b8e2f0cb75fa2fc0").unwrap();
+
+        assert_eq!(tx.chain_id, Some(111));
+        assert_eq!(tx.from, sender());
+        assert_eq!(tx.input, vec![0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(tx.nonce, 1);
+    }
+
+    #[test]
+    fn decodes_legacy_without_chain_id() {
+        let tx = decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000801ca0d47644539acec3da5e3ecf5fe8863c628a9c97e8b71e9ea9167a6f4f83c03c32a061d2351228dc95b0fdf5f056257e2d68beb9be7157124364810316d729db9756").unwrap();
+
+        assert_eq!(tx.chain_id, None);
+        assert_eq!(tx.from, sender());
+    }
+
+    #[test]
+    fn decodes_dynamic_fee_deployment() {
+        let tx = decode("02f85c6f03843b9aca008477359400830186a08005856080604052c080a0a7bc01ecd959bb68172c047180b3eaa4a1a328cb1165005979f131e9a958da72a05dbb7bbd8af999f406cfd57131dbf4fcec62223a5ee95e13b45d73677efa367c").unwrap();
+
+        assert_eq!(tx.chain_id, Some(111));
+        assert_eq!(tx.from, sender());
+        assert_eq!(tx.to, None);
+        assert_eq!(tx.value, U256::from(5_u64));
+        assert_eq!(tx.gas, 100_000);
+        // maxFeePerGas
+        assert_eq!(tx.gas_price, U256::from(2_000_000_000_u64));
+        assert_eq!(tx.nonce, 3);
+        assert_eq!(tx.input, hex::decode("6080604052").unwrap());
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        // the value of the EIP-155 example is changed, the signature recovers another sender
+        let tx = decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400018025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+
+        assert!(tx.map_or(true, |tx| tx.from != sender()));
+    }
+
+    #[test]
+    fn rejects_unsupported_type() {
+        assert!(matches!(
+            decode("03c0"),
+            Err(RawTransactionError::UnsupportedType(0x03))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_list() {
+        assert!(matches!(
+            decode("c3010203"),
+            Err(RawTransactionError::Rlp(_))
+        ));
+        assert!(decode("").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Test decoder of the `delta` structLog format requested with
+//! `"tracerConfig": {"format": "delta"}`. Every step of the format reports only
+//! the changes of the stack, memory, storage and return data since the previous step;
+//! `Decoder` replays them to restore the full snapshots of the default format.
+//!
+//! The tracer doesn't decode the format, the tests check that the decoded `delta` output
+//! matches the full structLogs.
+
+use std::collections::BTreeMap;
+
+use ethnum::U256;
+use evm_loader::types::hexbytes::HexBytes;
+use serde::{Deserialize, Deserializer};
+
+/// Step of the `delta` format
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaLog {
+    pub pc: u64,
+    pub op: String,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: u32,
+    pub memory: Option<MemoryDelta>,
+    pub stack: Option<StackDelta>,
+    /// `Some(None)` once the return data is cleared
+    #[serde(default, deserialize_with = "present")]
+    pub return_data: Option<Option<Vec<u8>>>,
+    pub storage: Option<StorageDelta>,
+    #[serde(default)]
+    pub refund: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StackDelta {
+    /// Number of the items removed from the top of the stack
+    pub pop: usize,
+    /// Items pushed after the removed ones, the top of the stack is the last item
+    pub push: Vec<U256>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemoryDelta {
+    /// New memory size, the memory is truncated or zero extended to it
+    pub size: usize,
+    #[serde(default)]
+    pub writes: Vec<MemoryWrite>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemoryWrite {
+    pub offset: usize,
+    pub data: HexBytes,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StorageDelta {
+    /// Slots set to a new value
+    pub set: BTreeMap<String, U256>,
+    /// Slots removed from the snapshot
+    pub clear: Vec<String>,
+}
+
+/// Full snapshots of a step, as reported by the default format
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub stack: Vec<U256>,
+    pub memory: Vec<u8>,
+    pub storage: BTreeMap<String, U256>,
+    pub return_data: Option<Vec<u8>>,
+}
+
+/// Applies the steps of a `structLogs` array in order
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    snapshot: Snapshot,
+}
+
+impl Decoder {
+    /// Returns the snapshots of the step
+    pub fn apply(&mut self, log: &DeltaLog) -> &Snapshot {
+        let snapshot = &mut self.snapshot;
+
+        if let Some(stack) = &log.stack {
+            let kept = snapshot.stack.len().saturating_sub(stack.pop);
+            snapshot.stack.truncate(kept);
+            snapshot.stack.extend_from_slice(&stack.push);
+        }
+
+        if let Some(memory) = &log.memory {
+            snapshot.memory.resize(memory.size, 0);
+            for write in &memory.writes {
+                let end = write.offset + write.data.0.len();
+                if snapshot.memory.len() < end {
+                    snapshot.memory.resize(end, 0);
+                }
+                snapshot.memory[write.offset..end].copy_from_slice(&write.data.0);
+            }
+        }
+
+        if let Some(storage) = &log.storage {
+            for key in &storage.clear {
+                snapshot.storage.remove(key);
+            }
+            snapshot
+                .storage
+                .extend(storage.set.iter().map(|(key, value)| (key.clone(), *value)));
+        }
+
+        if let Some(return_data) = &log.return_data {
+            snapshot.return_data = return_data.clone();
+        }
+
+        snapshot
+    }
+}
+
+/// Decodes the whole `structLogs` array
+pub fn decode(logs: &[DeltaLog]) -> Vec<Snapshot> {
+    let mut decoder = Decoder::default();
+    logs.iter().map(|log| decoder.apply(log).clone()).collect()
+}
+
+/// Tells a `null` field from the missing one
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
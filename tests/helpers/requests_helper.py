@@ -31,6 +31,31 @@ def get_tx_info(tx_hex) -> str:
     })
 
 
+def get_tx_receipt(tx_hex) -> str:
+    return json.dumps({
+        "jsonrpc": "2.0",
+        "method": "eth_getTransactionReceipt",
+        "params": [tx_hex],
+        "id": 1
+    })
+
+
+def get_raw_tx(tx_hex) -> str:
+    return json.dumps({
+        "jsonrpc": "2.0",
+        "method": "eth_getRawTransactionByHash",
+        "params": [tx_hex],
+        "id": 1
+    })
+
+
+def render_request(template: str, values: dict) -> dict:
+    """Replaces the quoted `{{name}}` placeholders of a request file with the JSON values"""
+    for name, value in values.items():
+        template = template.replace(f'"{{{{{name}}}}}"', json.dumps(value))
+    return json.loads(template)
+
+
 STORAGE_SOLIDITY_SOURCE = '''
 pragma solidity >=0.4.0;
 /**
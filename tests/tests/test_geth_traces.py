@@ -3,7 +3,8 @@ import json
 import os
 from os import listdir
 from os.path import isfile, join
-from helpers.requests_helper import set_correct_params, send_trace_request, get_tx_info
+from helpers.requests_helper import set_correct_params, send_trace_request, get_tx_info, get_tx_receipt, \
+    render_request
 from parameterized import parameterized
 from helpers.soft_assertion import assert_all
 from helpers.test_helper import validate_type_by_scheme
@@ -14,6 +15,8 @@ ROOT_DIR = os.path.dirname(os.path.abspath(__file__))
 RESOURCES = f'{ROOT_DIR}/../resources/requests/gETH'
 REQUESTS_TRACE_CALL = [f for f in listdir(RESOURCES) if isfile(join(RESOURCES, f)) and 'debug_trace_call' in f]
 REQUESTS_TRANSACTION = [f for f in listdir(RESOURCES) if isfile(join(RESOURCES, f)) and 'debug_trace_transaction' in f]
+REQUESTS_STATE = [f for f in listdir(RESOURCES) if isfile(join(RESOURCES, f)) and
+                  ('debug_storage_range_at' in f or 'debug_get_modified_accounts' in f)]
 
 
 @parameterized.expand(**level_test_parameters)
@@ -40,9 +43,15 @@ def test_geth_debug_trace_call(tx_hex, url_node, url_trace, role):
 
     for file in REQUESTS_TRACE_CALL:
         with open(f'{RESOURCES}/{file}') as req:
-            file_payload: dict = json.loads(req.read())
+            known = {key: value for key, value in params[0].items() if value is not None}
+            file_payload: dict = render_request(req.read(), known)
             file_payload['params'][0] = set_correct_params(file_payload['params'][0], params[0])
             file_payload['params'][1] = params[1]
+            if len(file_payload['params']) > 2:
+                # the accounts the transaction has no address for, e.g. `to` of a deployment, are not overridden
+                overrides = file_payload['params'][2].get('stateOverrides', {})
+                for account in [account for account in overrides if account.startswith('{{')]:
+                    del overrides[account]
 
             payload = json.dumps(file_payload)
 
@@ -91,3 +100,38 @@ def test_geth_debug_transaction_call(tx_hex, url_node, url_trace, role):
         print('\n' + blue_text(f'Scheme to validate {method_scheme}'), end='\n')
 
         validate_type_by_scheme(response_dict['result'], method_scheme, 'result')
+
+
+@parameterized.expand(**level_test_parameters)
+@assert_all()
+def test_geth_debug_state(tx_hex, url_node, url_trace, role):
+    result = send_trace_request(url_node, get_tx_info(tx_hex))['result']
+    receipt = send_trace_request(url_node, get_tx_receipt(tx_hex))['result']
+    values = {
+        "blockHash": result.get('blockHash'),
+        "blockNumber": result.get('blockNumber'),
+        "address": receipt.get('contractAddress') or result.get('to'),
+    }
+
+    for file in REQUESTS_STATE:
+        with open(f'{RESOURCES}/{file}') as req:
+            file_payload = render_request(req.read(), values)
+            payload = json.dumps(file_payload)
+
+        print('\n' + blue_text('Request'), end='\n')
+
+        pprint(file_payload)
+
+        response_dict = send_trace_request(url_trace, payload)
+
+        assert response_dict, 'There are no response for trace request'
+        assert not response_dict.get('error'), 'There is error in response for trace request'
+
+        method_scheme = f'{role}_{file}'
+        print('\n' + blue_text('Reply'), end='\n')
+
+        pprint(response_dict)
+
+        print('\n' + blue_text(f'Scheme to validate {method_scheme}'), end='\n')
+
+        validate_type_by_scheme(response_dict['result'], method_scheme, 'result')
@@ -0,0 +1,69 @@
+from pprint import pprint
+import json
+import os
+from os import listdir
+from os.path import isfile, join
+from helpers.requests_helper import send_trace_request, get_tx_info, get_raw_tx, render_request
+from parameterized import parameterized
+from helpers.soft_assertion import assert_all
+from helpers.test_helper import validate_type_by_scheme
+from helpers.testing_parameterization import level_test_parameters
+from helpers import blue_text, yellow_text
+
+ROOT_DIR = os.path.dirname(os.path.abspath(__file__))
+RESOURCES = f'{ROOT_DIR}/../resources/requests/open_eth'
+REQUESTS = sorted(f for f in listdir(RESOURCES) if isfile(join(RESOURCES, f)))
+
+
+def request_values(tx_hex, url_node) -> dict:
+    result = send_trace_request(url_node, get_tx_info(tx_hex))['result']
+    raw_tx = send_trace_request(url_node, get_raw_tx(tx_hex)).get('result')
+    return {
+        "hash": tx_hex,
+        "hash_2": tx_hex,
+        "blockNumber": result.get('blockNumber'),
+        "from": result.get('from'),
+        "to": result.get('to'),
+        "input": result.get('input'),
+        "gas": result.get('gas'),
+        "gasPrice": result.get('gasPrice'),
+        "nonce": result.get('nonce'),
+        "rawTransaction": raw_tx,
+    }
+
+
+@parameterized.expand(**level_test_parameters)
+@assert_all()
+def test_open_eth_traces(tx_hex, url_node, url_trace, role):
+    print("tx_hex, role", tx_hex, role)
+
+    values = request_values(tx_hex, url_node)
+    pprint(values)
+
+    for file in REQUESTS:
+        with open(f'{RESOURCES}/{file}') as req:
+            template = req.read()
+
+        if '{{rawTransaction}}' in template and values['rawTransaction'] is None:
+            print(yellow_text(f'Warning: the node returned no raw transaction, {file} is skipped'))
+            continue
+
+        file_payload = render_request(template, values)
+        payload = json.dumps(file_payload)
+
+        print('\n' + blue_text('Request'), end='\n')
+
+        pprint(file_payload)
+
+        response_dict = send_trace_request(url_trace, payload)
+
+        assert response_dict, 'There are no response for trace request'
+        assert not response_dict.get('error'), 'There is error in response for trace request'
+
+        method_scheme = f'{role}_{file}'
+        print('\n' + blue_text('Reply'), end='\n')
+
+        pprint(response_dict)
+
+        print('\n' + blue_text(f'Scheme to validate {method_scheme}'), end='\n')
+        validate_type_by_scheme(response_dict['result'], method_scheme, 'result')